@@ -15,7 +15,7 @@
  *  along with Mendel Vectorizer.  If not, see <https://www.gnu.org/licenses/>.
 */
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq)]
 pub struct Point {
     pub x: f64,
     pub y: f64,
@@ -31,6 +31,20 @@ impl Point {
             y: (self.y + other.y) / 2.0,
         }
     }
+
+    /* Distancia al segmento que une a y b */
+    pub fn distance_to_segment(&self, a: &Point, b: &Point) -> f64 {
+        let length = a.distance(b);
+        if length == 0.0 {
+            return self.distance(a);
+        }
+        let t = ((self.x - a.x) * (b.x - a.x) + (self.y - a.y) * (b.y - a.y)) / length.powf(2.0);
+        let t = t.clamp(0.0, 1.0);
+        self.distance(&Point {
+            x: a.x + t * (b.x - a.x),
+            y: a.y + t * (b.y - a.y),
+        })
+    }
 }
 
 #[derive(Clone)]
@@ -49,6 +63,113 @@ impl<'a> Bezier {
             position: 0.0,
         }
     }
+
+    pub fn reversed(&self) -> Bezier {
+        Bezier {
+            start: self.end,
+            control1: self.control2,
+            control2: self.control1,
+            end: self.start,
+        }
+    }
+
+    /* De Casteljau */
+    pub fn split(&self) -> (Bezier, Bezier) {
+        let p01 = self.start.middle(&self.control1);
+        let p12 = self.control1.middle(&self.control2);
+        let p23 = self.control2.middle(&self.end);
+        let p012 = p01.middle(&p12);
+        let p123 = p12.middle(&p23);
+        let middle = p012.middle(&p123);
+        (
+            Bezier {
+                start: self.start,
+                control1: p01,
+                control2: p012,
+                end: middle,
+            },
+            Bezier {
+                start: middle,
+                control1: p123,
+                control2: p23,
+                end: self.end,
+            },
+        )
+    }
+
+    /* Polilínea que se separa de la curva como mucho tolerance */
+    pub fn flatten(&self, tolerance: f64) -> Vec<Point> {
+        let mut points = vec![self.start];
+        self.flatten_into(tolerance, &mut points, 0);
+        points
+    }
+
+    fn flatten_into(&self, tolerance: f64, points: &mut Vec<Point>, depth: u32) {
+        let flat = self.control1.distance_to_segment(&self.start, &self.end) <= tolerance
+            && self.control2.distance_to_segment(&self.start, &self.end) <= tolerance;
+        if flat || depth >= 16 {
+            points.push(self.end);
+        } else {
+            let (left, right) = self.split();
+            left.flatten_into(tolerance, points, depth + 1);
+            right.flatten_into(tolerance, points, depth + 1);
+        }
+    }
+}
+
+pub fn is_closed(contour: &[Bezier]) -> bool {
+    contour.len() > 1 && contour[0].start == contour[contour.len() - 1].end
+}
+
+pub fn reverse(contour: &[Bezier]) -> Vec<Bezier> {
+    contour.iter().rev().map(Bezier::reversed).collect()
+}
+
+fn polygon(contour: &[Bezier]) -> Vec<Point> {
+    contour
+        .iter()
+        .flat_map(|line| line.flatten(0.5).into_iter().skip(1))
+        .collect()
+}
+
+/* Positiva si el contorno va en el sentido de las agujas del reloj (eje Y hacia abajo) */
+pub fn signed_area(contour: &[Bezier]) -> f64 {
+    let points = polygon(contour);
+    let mut area = 0.0;
+    for (i, a) in points.iter().enumerate() {
+        let b = &points[(i + 1) % points.len()];
+        area += a.x * b.y - b.x * a.y;
+    }
+    area / 2.0
+}
+
+pub fn contains(contour: &[Bezier], point: &Point) -> bool {
+    let points = polygon(contour);
+    let mut inside = false;
+    for (i, a) in points.iter().enumerate() {
+        let b = &points[(i + 1) % points.len()];
+        if (a.y > point.y) != (b.y > point.y)
+            && point.x < a.x + (point.y - a.y) * (b.x - a.x) / (b.y - a.y)
+        {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+/* Agrupa las curvas consecutivas que comparten extremos */
+pub fn contours(lines: &[Bezier]) -> Vec<&[Bezier]> {
+    let mut contours = Vec::new();
+    let mut first = 0;
+    for i in 1..=lines.len() {
+        if i == lines.len() || lines[i - 1].end != lines[i].start {
+            if first < i {
+                contours.push(&lines[first..i]);
+            }
+            first = i;
+        }
+    }
+    contours
 }
 
 pub struct BezierIter<'a> {
@@ -0,0 +1,400 @@
+/*
+ *  This file is part of Mendel Vectorizer.
+ *
+ *  Mendel Vectorizer is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  Mendel Vectorizer is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with Mendel Vectorizer.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use crate::bezier::{Bezier, Point};
+use crate::genetic::{self, Event, GeneticConfig, Picture, SegmentResult};
+use crate::segment::Segment;
+
+use rand::prelude::*;
+use rand_distr::StandardNormal;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+/* Los genes son las coordenadas de los dos puntos de control */
+const N: usize = 4;
+
+type Vector = [f64; N];
+type Matrix = [[f64; N]; N];
+
+/* Generaciones sin mejorar antes de reiniciar con el doble de población (IPOP-CMA-ES) */
+const STAGNATION_GENERATIONS: usize = 20;
+
+fn genes(line: &Bezier) -> Vector {
+    [
+        line.control1.x,
+        line.control1.y,
+        line.control2.x,
+        line.control2.y,
+    ]
+}
+
+fn curve(segment: &Segment, x: &Vector) -> Bezier {
+    Bezier {
+        start: segment.start,
+        end: segment.end,
+        control1: Point { x: x[0], y: x[1] },
+        control2: Point { x: x[2], y: x[3] },
+    }
+}
+
+/* CMA-ES, según "The CMA Evolution Strategy: A Tutorial" de Nikolaus Hansen.
+ * Mismo fitness y criterios de parada que el algoritmo genético, None si se cancela */
+pub fn algorithm(
+    image: &Picture,
+    segment: Segment,
+    previous: Option<Bezier>,
+    config: &GeneticConfig,
+    cancel: &AtomicBool,
+    progress: &dyn Fn(Event),
+) -> Option<SegmentResult> {
+    if cancel.load(Ordering::Relaxed) {
+        return None;
+    }
+    progress(Event::Started(segment));
+    let started = Instant::now();
+    let mut rng = StdRng::seed_from_u64(genetic::segment_seed(
+        config.seed,
+        &segment.start,
+        &segment.end,
+    ));
+
+    /* Se parte de la mejor curva de la población inicial del algoritmo genético */
+    let population = genetic::initial_population(image, &segment, previous, config, &mut rng);
+    let mut scored: Vec<(f64, Bezier)> = population
+        .into_iter()
+        .map(|line| (genetic::evaluate(image, &line, config), line))
+        .collect();
+    scored.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
+    progress(Event::Generation {
+        segment,
+        generation: 0,
+        statistics: genetic::statistics(&scored),
+        elapsed: started.elapsed(),
+    });
+
+    /* Con la población por defecto (8) se queda en óptimos locales */
+    let n = N as f64;
+    let chi = n.sqrt() * (1.0 - 1.0 / (4.0 * n) + 1.0 / (21.0 * n * n));
+    let mut p = Parameters::new(config.cmaes_population.max(4));
+
+    let (mut best_score, mut best) = scored.swap_remove(0);
+    let initial_sigma = (segment.start.distance(&segment.end) * config.mutation_scale).max(1.0);
+    let mut mean = genes(&best);
+    let mut sigma = initial_sigma;
+    let mut pc = [0.0; N];
+    let mut ps = [0.0; N];
+    let mut covariance = identity();
+
+    let mut generations = 0;
+    /* Generaciones desde el último reinicio */
+    let mut iteration: usize = 0;
+    let mut stagnation = 0;
+    while best_score < config.target_score && generations < config.max_generations {
+        if cancel.load(Ordering::Relaxed) {
+            return None;
+        }
+        generations += 1;
+        iteration += 1;
+        let Parameters {
+            lambda,
+            ref weights,
+            mueff,
+            cc,
+            cs,
+            c1,
+            cmu,
+            damps,
+        } = p;
+
+        /* C = B D² Bᵀ */
+        let (eigenvalues, b) = eigen(covariance);
+        let d: Vector = eigenvalues.map(|e| e.max(1e-20).sqrt());
+
+        let mut offspring: Vec<(f64, Vector)> = (0..lambda)
+            .map(|_| {
+                let z: Vector = [(); N].map(|_| rng.sample(StandardNormal));
+                let y = multiply(&b, &std::array::from_fn(|i| d[i] * z[i]));
+                let x: Vector = std::array::from_fn(|i| mean[i] + sigma * y[i]);
+                (genetic::evaluate(image, &curve(&segment, &x), config), x)
+            })
+            .collect();
+        offspring.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
+        if offspring[0].0 > best_score {
+            best_score = offspring[0].0;
+            best = curve(&segment, &offspring[0].1);
+            stagnation = 0;
+        } else {
+            stagnation += 1;
+        }
+
+        let old = mean;
+        mean = [0.0; N];
+        for (w, (_, x)) in weights.iter().zip(&offspring) {
+            for i in 0..N {
+                mean[i] += w * x[i];
+            }
+        }
+        let yw: Vector = std::array::from_fn(|i| (mean[i] - old[i]) / sigma);
+
+        /* C^-1/2 = B D⁻¹ Bᵀ */
+        let bty = multiply(&transpose(&b), &yw);
+        let whitened = multiply(&b, &std::array::from_fn(|i| bty[i] / d[i]));
+        for i in 0..N {
+            ps[i] = (1.0 - cs) * ps[i] + (cs * (2.0 - cs) * mueff).sqrt() * whitened[i];
+        }
+        let ps_norm = norm(&ps);
+        let hsig = ps_norm / (1.0 - (1.0 - cs).powi(2 * iteration as i32)).sqrt() / chi
+            < 1.4 + 2.0 / (n + 1.0);
+        let hsig = if hsig { 1.0 } else { 0.0 };
+        for i in 0..N {
+            pc[i] = (1.0 - cc) * pc[i] + hsig * (cc * (2.0 - cc) * mueff).sqrt() * yw[i];
+        }
+
+        let mut updated = [[0.0; N]; N];
+        for i in 0..N {
+            for j in 0..N {
+                let rank_mu: f64 = weights
+                    .iter()
+                    .zip(&offspring)
+                    .map(|(w, (_, x))| w * (x[i] - old[i]) * (x[j] - old[j]) / (sigma * sigma))
+                    .sum();
+                updated[i][j] = (1.0 - c1 - cmu) * covariance[i][j]
+                    + c1 * (pc[i] * pc[j] + (1.0 - hsig) * cc * (2.0 - cc) * covariance[i][j])
+                    + cmu * rank_mu;
+            }
+        }
+        covariance = updated;
+        sigma *= ((cs / damps) * (ps_norm / chi - 1.0)).exp();
+
+        let population: Vec<(f64, Bezier)> = offspring
+            .iter()
+            .map(|(score, x)| (*score, curve(&segment, x)))
+            .collect();
+        progress(Event::Generation {
+            segment,
+            generation: generations,
+            statistics: genetic::statistics(&population),
+            elapsed: started.elapsed(),
+        });
+        if config.snapshot_every > 0 && generations % config.snapshot_every == 0 {
+            let mut population: Vec<Bezier> =
+                population.into_iter().map(|(_, line)| line).collect();
+            population.insert(0, best.clone());
+            progress(genetic::snapshot(segment, generations, &population));
+        }
+
+        /* Se reinicia desde otra curva de la población inicial, sin pasar de la población del genético */
+        if stagnation >= STAGNATION_GENERATIONS {
+            p = Parameters::new((lambda * 2).min(config.population).max(lambda));
+            mean = scored
+                .choose(&mut rng)
+                .map_or(genes(&best), |(_, line)| genes(line));
+            sigma = initial_sigma;
+            pc = [0.0; N];
+            ps = [0.0; N];
+            covariance = identity();
+            iteration = 0;
+            stagnation = 0;
+        }
+    }
+    let result = SegmentResult::new(
+        image,
+        segment.contour,
+        segment.index,
+        best,
+        generations,
+        config,
+    );
+    progress(Event::Finished(result.clone()));
+    Some(result)
+}
+
+/* Pesos de la recombinación y tasas de aprendizaje por defecto para λ curvas por generación */
+struct Parameters {
+    lambda: usize,
+    weights: Vec<f64>,
+    mueff: f64,
+    cc: f64,
+    cs: f64,
+    c1: f64,
+    cmu: f64,
+    damps: f64,
+}
+
+impl Parameters {
+    fn new(lambda: usize) -> Parameters {
+        let n = N as f64;
+        let mu = lambda / 2;
+        let weights: Vec<f64> = (1..=mu)
+            .map(|i| (mu as f64 + 0.5).ln() - (i as f64).ln())
+            .collect();
+        let total: f64 = weights.iter().sum();
+        let weights: Vec<f64> = weights.iter().map(|w| w / total).collect();
+        let mueff = 1.0 / weights.iter().map(|w| w * w).sum::<f64>();
+        let cc = (4.0 + mueff / n) / (n + 4.0 + 2.0 * mueff / n);
+        let cs = (mueff + 2.0) / (n + mueff + 5.0);
+        let c1 = 2.0 / ((n + 1.3).powi(2) + mueff);
+        let cmu = (1.0 - c1).min(2.0 * (mueff - 2.0 + 1.0 / mueff) / ((n + 2.0).powi(2) + mueff));
+        let damps = 1.0 + 2.0 * (((mueff - 1.0) / (n + 1.0)).sqrt() - 1.0).max(0.0) + cs;
+        Parameters {
+            lambda,
+            weights,
+            mueff,
+            cc,
+            cs,
+            c1,
+            cmu,
+            damps,
+        }
+    }
+}
+
+fn identity() -> Matrix {
+    std::array::from_fn(|i| std::array::from_fn(|j| if i == j { 1.0 } else { 0.0 }))
+}
+
+fn transpose(a: &Matrix) -> Matrix {
+    std::array::from_fn(|i| std::array::from_fn(|j| a[j][i]))
+}
+
+fn multiply(a: &Matrix, x: &Vector) -> Vector {
+    std::array::from_fn(|i| (0..N).map(|j| a[i][j] * x[j]).sum())
+}
+
+fn norm(x: &Vector) -> f64 {
+    x.iter().map(|v| v * v).sum::<f64>().sqrt()
+}
+
+/* Autovalores y autovectores (en columnas) de una matriz simétrica, por el método de Jacobi */
+fn eigen(mut a: Matrix) -> (Vector, Matrix) {
+    let mut v = identity();
+    for _ in 0..50 {
+        let off: f64 = (0..N)
+            .flat_map(|p| (p + 1..N).map(move |q| (p, q)))
+            .map(|(p, q)| a[p][q] * a[p][q])
+            .sum();
+        if off < 1e-30 {
+            break;
+        }
+        for p in 0..N {
+            for q in p + 1..N {
+                if a[p][q] == 0.0 {
+                    continue;
+                }
+                let theta = (a[q][q] - a[p][p]) / (2.0 * a[p][q]);
+                let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+                let c = 1.0 / (t * t + 1.0).sqrt();
+                let s = t * c;
+                /* A = Jᵀ A J, V = V J */
+                let rotate_columns = |m: &mut Matrix| {
+                    for row in m.iter_mut() {
+                        let (mp, mq) = (row[p], row[q]);
+                        row[p] = c * mp - s * mq;
+                        row[q] = s * mp + c * mq;
+                    }
+                };
+                rotate_columns(&mut a);
+                let (ap, aq) = (a[p], a[q]);
+                a[p] = std::array::from_fn(|k| c * ap[k] - s * aq[k]);
+                a[q] = std::array::from_fn(|k| s * ap[k] + c * aq[k]);
+                rotate_columns(&mut v);
+            }
+        }
+    }
+    (std::array::from_fn(|i| a[i][i]), v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GrayImage, Luma};
+
+    fn p(x: f64, y: f64) -> Point {
+        Point { x, y }
+    }
+
+    #[test]
+    fn eigen_reconstruction() {
+        let a: Matrix = [
+            [4.0, 1.0, 0.5, 0.0],
+            [1.0, 3.0, 0.2, 0.1],
+            [0.5, 0.2, 2.0, 0.3],
+            [0.0, 0.1, 0.3, 1.0],
+        ];
+        let (values, vectors) = eigen(a);
+        for i in 0..N {
+            for j in 0..N {
+                let value: f64 = (0..N)
+                    .map(|k| vectors[i][k] * values[k] * vectors[j][k])
+                    .sum();
+                assert!((value - a[i][j]).abs() < 1e-9);
+            }
+        }
+    }
+
+    /* Trazo de 3 píxeles de ancho a lo largo de la curva */
+    fn stroke(curve: &Bezier) -> GrayImage {
+        let mut image = GrayImage::from_pixel(110, 70, Luma([255]));
+        for i in 0..=1000 {
+            let t = i as f64 / 1000.0;
+            let s = 1.0 - t;
+            let (a, b, c, d) = (s * s * s, 3.0 * s * s * t, 3.0 * s * t * t, t * t * t);
+            let x =
+                a * curve.start.x + b * curve.control1.x + c * curve.control2.x + d * curve.end.x;
+            let y =
+                a * curve.start.y + b * curve.control1.y + c * curve.control2.y + d * curve.end.y;
+            for dy in -1..=1 {
+                for dx in -1..=1 {
+                    let (x, y) = (x as i64 + dx, y as i64 + dy);
+                    if x >= 0 && y >= 0 && x < 110 && y < 70 {
+                        image.put_pixel(x as u32, y as u32, Luma([0]));
+                    }
+                }
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn synthetic_stroke() {
+        let curve = Bezier {
+            start: p(10.0, 60.0),
+            control1: p(30.0, 0.0),
+            control2: p(80.0, 0.0),
+            end: p(100.0, 60.0),
+        };
+        let config = GeneticConfig::default();
+        let image = Picture::new(stroke(&curve), &config);
+        let segment = Segment {
+            contour: 0,
+            index: 0,
+            start: curve.start,
+            end: curve.end,
+        };
+        let result = algorithm(
+            &image,
+            segment,
+            None,
+            &config,
+            &AtomicBool::new(false),
+            &|_| {},
+        )
+        .unwrap();
+        assert!(result.score >= config.target_score);
+    }
+}
@@ -15,11 +15,9 @@
  *  along with Mendel Vectorizer.  If not, see <https://www.gnu.org/licenses/>.
 */
 
-use image::imageops::colorops::grayscale;
+use image::GrayImage;
 use imageproc::corners::{corners_fast9, Corner};
 
-pub fn fast9(img: String) -> Vec<Corner> {
-    let img = image::open(img).unwrap();
-    let img = grayscale(&img);
-    corners_fast9(&img, 50)
+pub fn fast9(img: &GrayImage) -> Vec<Corner> {
+    corners_fast9(img, 50)
 }
@@ -15,28 +15,877 @@
  *  along with Mendel Vectorizer.  If not, see <https://www.gnu.org/licenses/>.
 */
 
-use crate::bezier::Bezier;
+use crate::bezier::{self, Bezier, Point};
+use crate::genetic::{
+    Crossover, Event, Fitness, GeneticConfig, Mutation, SegmentResult, Selection,
+};
 use std::fs::File;
 use std::io::Write;
 use std::path::PathBuf;
 
-pub fn export(lines: &[Bezier], filename: PathBuf) {
-    let mut svg = String::from("<svg width=\"\" height=\"\" xmlns=\"http://www.w3.org/2000/svg\">");
-    for line in lines {
-        svg += &format!(
-            "<path d=\"M{} {} C {} {}, {} {}, {} {}\" style=\"stroke: black;fill:none\"/>",
-            line.start.x,
-            line.start.y,
-            line.control1.x,
-            line.control1.y,
-            line.control2.x,
-            line.control2.y,
-            line.end.x,
-            line.end.y
-        );
+pub struct SvgOptions {
+    /* Número de decimales de las coordenadas */
+    pub precision: usize,
+    /* Comandos relativos (c, s) en vez de absolutos (C, S) */
+    pub relative: bool,
+    /* Rellena los contornos en vez de trazarlos */
+    pub fill: Option<FillRule>,
+    /* Incluye la imagen original y los parámetros usados, para revisar el resultado */
+    pub source: Option<SvgSource>,
+}
+
+#[derive(Copy, Clone)]
+pub enum FillRule {
+    EvenOdd,
+    NonZero,
+}
+
+pub struct SvgSource {
+    pub input: String,
+    /* La imagen original ya codificada, el fichero puede haber cambiado al exportar */
+    pub png: Vec<u8>,
+    /* Tamaño de la imagen usada por el algoritmo, tras el preprocesado */
+    pub width: u32,
+    pub height: u32,
+    pub config: GeneticConfig,
+    pub optimizer: String,
+}
+
+pub fn export(contours: &[Vec<Bezier>], filename: PathBuf, options: &SvgOptions) {
+    let mut paths = String::new();
+    match options.fill {
+        None => {
+            for contour in contours {
+                paths += &format!(
+                    "<path d=\"{}\" style=\"stroke: black;fill:none\"/>",
+                    path_data(contour, options)
+                );
+            }
+        }
+        /* Un único path para que fill-rule tenga en cuenta los agujeros */
+        Some(FillRule::EvenOdd) => {
+            let data: Vec<String> = contours
+                .iter()
+                .map(|contour| path_data(contour, options))
+                .collect();
+            paths += &format!(
+                "<path d=\"{}\" style=\"stroke:none;fill:black;fill-rule:evenodd\"/>",
+                data.concat()
+            );
+        }
+        Some(FillRule::NonZero) => {
+            let data: Vec<String> = oriented(contours)
+                .iter()
+                .map(|contour| path_data(contour, options))
+                .collect();
+            paths += &format!(
+                "<path d=\"{}\" style=\"stroke:none;fill:black;fill-rule:nonzero\"/>",
+                data.concat()
+            );
+        }
     }
-    svg += "</svg>";
+
+    let svg = match &options.source {
+        None => format!(
+            "<svg width=\"\" height=\"\" xmlns=\"http://www.w3.org/2000/svg\">{}</svg>",
+            paths
+        ),
+        Some(source) => {
+            let (width, height) = (source.width, source.height);
+            format!(
+                concat!(
+                    "<svg width=\"{width}\" height=\"{height}\" xmlns=\"http://www.w3.org/2000/svg\" ",
+                    "xmlns:xlink=\"http://www.w3.org/1999/xlink\" ",
+                    "xmlns:inkscape=\"http://www.inkscape.org/namespaces/inkscape\" ",
+                    "xmlns:sodipodi=\"http://sodipodi.sourceforge.net/DTD/sodipodi-0.dtd\" ",
+                    "xmlns:mendel=\"https://github.com/aarroyoc/mendel-vectorizer\">",
+                    "<desc>Vectorized from {input} with Mendel Vectorizer {version}</desc>",
+                    "<metadata><mendel:run input=\"{input}\" version=\"{version}\"{run}/></metadata>",
+                    "<g id=\"source\" inkscape:groupmode=\"layer\" inkscape:label=\"Source\" ",
+                    "sodipodi:insensitive=\"true\" opacity=\"0.5\">",
+                    "<image width=\"{width}\" height=\"{height}\" xlink:href=\"data:image/png;base64,{png}\"/></g>",
+                    "<g id=\"curves\" inkscape:groupmode=\"layer\" inkscape:label=\"Curves\">{paths}</g></svg>"
+                ),
+                width = width,
+                height = height,
+                input = escape(&source.input),
+                version = env!("CARGO_PKG_VERSION"),
+                run = run_attributes(&source.config, &source.optimizer),
+                png = base64::encode(&source.png),
+                paths = paths
+            )
+        }
+    };
 
     let mut file = File::create(filename).unwrap();
     file.write_all(svg.as_bytes()).unwrap();
 }
+
+/* Todos los parámetros, con los nombres de las opciones de la línea de comandos */
+fn run_attributes(config: &GeneticConfig, optimizer: &str) -> String {
+    let seeding = [
+        (config.seeding.straight, "straight"),
+        (config.seeding.dark, "dark"),
+        (config.seeding.previous, "previous"),
+        (config.seeding.fit, "fit"),
+    ];
+    let init: Vec<&str> = seeding
+        .iter()
+        .filter(|(enabled, _)| *enabled)
+        .map(|(_, name)| *name)
+        .collect();
+
+    let mut attributes = vec![
+        ("optimizer", optimizer.to_string()),
+        ("seed", config.seed.to_string()),
+        ("population", config.population.to_string()),
+        ("good-ones", config.good_ones.to_string()),
+        (
+            "init",
+            if init.is_empty() {
+                "none".to_string()
+            } else {
+                init.join(",")
+            },
+        ),
+    ];
+    match config.selection {
+        Selection::Truncation => attributes.push(("selection", "truncation".to_string())),
+        Selection::Tournament(size) => {
+            attributes.push(("selection", "tournament".to_string()));
+            attributes.push(("tournament-size", size.to_string()));
+        }
+        Selection::Roulette => attributes.push(("selection", "roulette".to_string())),
+        Selection::Rank => attributes.push(("selection", "rank".to_string())),
+    }
+    attributes.push(("elitism", config.elitism.to_string()));
+    match config.crossover {
+        Crossover::Blend(alpha) => {
+            attributes.push(("crossover", "blend".to_string()));
+            attributes.push(("blend-alpha", alpha.to_string()));
+        }
+        Crossover::SimulatedBinary(eta) => {
+            attributes.push(("crossover", "sbx".to_string()));
+            attributes.push(("sbx-eta", eta.to_string()));
+        }
+        Crossover::Arithmetic => attributes.push(("crossover", "arithmetic".to_string())),
+    }
+    let mutation = match config.mutation {
+        Mutation::Fixed => "fixed",
+        Mutation::Schedule => "schedule",
+        Mutation::OneFifth => "one-fifth",
+    };
+    let fitness = match config.fitness {
+        Fitness::Stroke => "stroke",
+        Fitness::Boundary => "boundary",
+        Fitness::Gradient => "gradient",
+    };
+    attributes.extend([
+        ("crossover-rate", config.crossover_rate.to_string()),
+        ("mutation-rate", config.mutation_rate.to_string()),
+        ("mutation", mutation.to_string()),
+        ("mutation-scale", config.mutation_scale.to_string()),
+        ("cmaes-population", config.cmaes_population.to_string()),
+        ("target-score", config.target_score.to_string()),
+        ("max-generations", config.max_generations.to_string()),
+        ("fitness", fitness.to_string()),
+        ("threshold", config.threshold.to_string()),
+        ("invert", config.invert.to_string()),
+        (
+            "parallel-evaluation",
+            config.parallel_evaluation.to_string(),
+        ),
+    ]);
+    attributes
+        .iter()
+        .map(|(name, value)| format!(" {}=\"{}\"", name, escape(value)))
+        .collect()
+}
+
+/* Con nonzero los agujeros tienen que ir en sentido contrario al contorno que los contiene */
+fn oriented(contours: &[Vec<Bezier>]) -> Vec<Vec<Bezier>> {
+    contours
+        .iter()
+        .enumerate()
+        .map(|(i, contour)| {
+            let depth = contours
+                .iter()
+                .enumerate()
+                .filter(|(j, other)| *j != i && bezier::contains(other, &contour[0].start))
+                .count();
+            let clockwise = bezier::signed_area(contour) > 0.0;
+            if clockwise == (depth % 2 == 0) {
+                contour.to_vec()
+            } else {
+                bezier::reverse(contour)
+            }
+        })
+        .collect()
+}
+
+fn path_data(contour: &[Bezier], options: &SvgOptions) -> String {
+    let precision = options.precision;
+    let round_point = |p: &Point| Point {
+        x: round(p.x, precision),
+        y: round(p.y, precision),
+    };
+    let epsilon = 0.5 * 10f64.powi(-(precision as i32));
+
+    let mut path = PathWriter::new(precision);
+    /* Si falta algún segmento del contorno, cada tramo empieza con su propio M */
+    let runs = bezier::contours(contour);
+    for run in &runs {
+        let mut current = round_point(&run[0].start);
+        path.command('M', &[current.x, current.y]);
+
+        let mut previous_control: Option<Point> = None;
+        for line in run.iter() {
+            let control1 = round_point(&line.control1);
+            let control2 = round_point(&line.control2);
+            let end = round_point(&line.end);
+
+            /* control1 es el reflejo del control2 anterior respecto al punto actual */
+            let smooth = previous_control.is_some_and(|previous| {
+                (2.0 * current.x - previous.x - control1.x).abs() < epsilon
+                    && (2.0 * current.y - previous.y - control1.y).abs() < epsilon
+            });
+            let mut points = Vec::new();
+            if !smooth {
+                points.push(control1);
+            }
+            points.push(control2);
+            points.push(end);
+
+            let mut values = Vec::new();
+            for point in points {
+                if options.relative {
+                    values.push(round(point.x - current.x, precision));
+                    values.push(round(point.y - current.y, precision));
+                } else {
+                    values.push(point.x);
+                    values.push(point.y);
+                }
+            }
+            let command = match (smooth, options.relative) {
+                (false, false) => 'C',
+                (false, true) => 'c',
+                (true, false) => 'S',
+                (true, true) => 's',
+            };
+            path.command(command, &values);
+
+            previous_control = Some(control2);
+            current = end;
+        }
+    }
+    if runs.len() == 1 && bezier::is_closed(contour) {
+        path.command(if options.relative { 'z' } else { 'Z' }, &[]);
+    }
+    path.data
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn round(value: f64, precision: usize) -> f64 {
+    let factor = 10f64.powi(precision as i32);
+    (value * factor).round() / factor
+}
+
+/* Escribe los datos de un path con el mínimo de separadores, como svgo */
+struct PathWriter {
+    data: String,
+    precision: usize,
+    last_command: Option<char>,
+    last_number: Option<String>,
+}
+
+impl PathWriter {
+    fn new(precision: usize) -> PathWriter {
+        PathWriter {
+            data: String::new(),
+            precision,
+            last_command: None,
+            last_number: None,
+        }
+    }
+
+    fn command(&mut self, command: char, values: &[f64]) {
+        /* Tras un M la repetición implícita es L, no se puede omitir */
+        if self.last_command != Some(command) || command == 'M' {
+            self.data.push(command);
+            self.last_command = Some(command);
+            self.last_number = None;
+        }
+        for value in values {
+            let number = format_number(*value, self.precision);
+            if let Some(last) = &self.last_number {
+                let implicit =
+                    number.starts_with('-') || (number.starts_with('.') && last.contains('.'));
+                if !implicit {
+                    self.data.push(' ');
+                }
+            }
+            self.data += &number;
+            self.last_number = Some(number);
+        }
+    }
+}
+
+fn format_number(value: f64, precision: usize) -> String {
+    let mut number = format!("{:.*}", precision, value);
+    if number.contains('.') {
+        number = number
+            .trim_end_matches('0')
+            .trim_end_matches('.')
+            .to_string();
+    }
+    if number == "-0" {
+        number = String::from("0");
+    }
+    if let Some(decimals) = number.strip_prefix("0.") {
+        number = format!(".{}", decimals);
+    } else if let Some(decimals) = number.strip_prefix("-0.") {
+        number = format!("-.{}", decimals);
+    }
+    number
+}
+
+pub struct ExportOptions {
+    pub svg: SvgOptions,
+    pub hpgl: HpglOptions,
+    pub tikz: TikzOptions,
+}
+
+/* El formato se elige según la extensión del fichero */
+pub fn export_file(results: &[SegmentResult], filename: PathBuf, options: &ExportOptions) {
+    let contours: Vec<Vec<Bezier>> = by_contour(results)
+        .map(|contour| contour.iter().map(|r| r.curve.clone()).collect())
+        .collect();
+    match filename.extension().and_then(|e| e.to_str()) {
+        Some("hpgl") | Some("plt") => export_hpgl(&contours, filename, &options.hpgl),
+        Some("json") => export_json(results, filename),
+        Some("tex") => export_tikz(&contours, filename, &options.tikz),
+        Some("js") => export_code(&contours, filename, CodeTarget::JavaScript),
+        Some("rs") => export_code(&contours, filename, CodeTarget::Cairo),
+        _ => export(&contours, filename, &options.svg),
+    }
+}
+
+/* Según el contorno de cada resultado y no por los extremos: dos contornos pueden tocarse
+ * y a un contorno le puede faltar un segmento cancelado */
+fn by_contour(results: &[SegmentResult]) -> impl Iterator<Item = &[SegmentResult]> {
+    results.chunk_by(|a, b| a.contour == b.contour)
+}
+
+/* Los píxeles se interpretan a 96 DPI, igual que en SVG */
+const MM_PER_PIXEL: f64 = 25.4 / 96.0;
+const HPGL_PENS: usize = 8;
+
+#[derive(Copy, Clone)]
+pub enum PenMode {
+    Contour,
+    Color,
+}
+
+pub struct HpglOptions {
+    /* Error máximo al aproximar las curvas con rectas, en milímetros */
+    pub tolerance: f64,
+    pub units_per_mm: f64,
+    pub pen: PenMode,
+}
+
+pub fn export_hpgl(contours: &[Vec<Bezier>], filename: PathBuf, options: &HpglOptions) {
+    /* HPGL tiene el eje Y hacia arriba */
+    let max_y = contours
+        .iter()
+        .flatten()
+        .flat_map(|line| [line.start.y, line.control1.y, line.control2.y, line.end.y])
+        .fold(0.0, f64::max);
+    let scale = MM_PER_PIXEL * options.units_per_mm;
+    let to_plotter = |point: &Point| {
+        (
+            (point.x * scale).round() as i64,
+            ((max_y - point.y) * scale).round() as i64,
+        )
+    };
+
+    let mut hpgl = String::from("IN;");
+    for (i, contour) in contours.iter().enumerate() {
+        let pen = match options.pen {
+            PenMode::Contour => i % HPGL_PENS + 1,
+            /* Todos los trazos son negros, así que es una única pluma */
+            PenMode::Color => 1,
+        };
+        if i == 0 || matches!(options.pen, PenMode::Contour) {
+            hpgl += &format!("SP{};", pen);
+        }
+
+        for run in bezier::contours(contour) {
+            let (x, y) = to_plotter(&run[0].start);
+            hpgl += &format!("PU{},{};PD", x, y);
+            let mut points = Vec::new();
+            for line in run.iter() {
+                for point in line
+                    .flatten(options.tolerance / MM_PER_PIXEL)
+                    .iter()
+                    .skip(1)
+                {
+                    let (x, y) = to_plotter(point);
+                    points.push(format!("{},{}", x, y));
+                }
+            }
+            hpgl += &points.join(",");
+            hpgl += ";";
+        }
+    }
+    hpgl += "PU;SP0;";
+
+    let mut file = File::create(filename).unwrap();
+    file.write_all(hpgl.as_bytes()).unwrap();
+}
+
+pub fn export_json(results: &[SegmentResult], filename: PathBuf) {
+    let point = |p: &Point| format!("{{\"x\":{},\"y\":{}}}", p.x, p.y);
+
+    let mut contours = Vec::new();
+    for contour in by_contour(results) {
+        let segments: Vec<String> = contour
+            .iter()
+            .map(|r| {
+                format!(
+                    "{{\"contour\":{},\"index\":{},\"start\":{},\"control1\":{},\"control2\":{},\"end\":{},\"score\":{},\"generations\":{},\"converged\":{},\"rms_error\":{},\"max_error\":{}}}",
+                    r.contour,
+                    r.index,
+                    point(&r.curve.start),
+                    point(&r.curve.control1),
+                    point(&r.curve.control2),
+                    point(&r.curve.end),
+                    r.score,
+                    r.generations,
+                    r.converged,
+                    r.error.map_or("null".to_string(), |e| e.rms.to_string()),
+                    r.error.map_or("null".to_string(), |e| e.max.to_string())
+                )
+            })
+            .collect();
+        contours.push(format!("{{\"segments\":[{}]}}", segments.join(",")));
+    }
+    let json = format!("{{\"contours\":[{}]}}", contours.join(","));
+
+    let mut file = File::create(filename).unwrap();
+    file.write_all(json.as_bytes()).unwrap();
+}
+
+/* Estadísticas de cada generación, en JSON lines si la extensión es .jsonl y si no en CSV */
+pub struct StatisticsLog {
+    file: File,
+    json: bool,
+}
+
+impl StatisticsLog {
+    pub fn create(filename: PathBuf) -> StatisticsLog {
+        let json = filename.extension().and_then(|e| e.to_str()) == Some("jsonl");
+        let mut file = File::create(filename).unwrap();
+        if !json {
+            writeln!(
+                file,
+                "contour,index,generation,best,mean,median,worst,diversity,elapsed"
+            )
+            .unwrap();
+        }
+        StatisticsLog { file, json }
+    }
+
+    pub fn write(&mut self, event: &Event) {
+        if let Event::Generation {
+            segment,
+            generation,
+            statistics: s,
+            elapsed,
+        } = event
+        {
+            if self.json {
+                writeln!(
+                    self.file,
+                    "{{\"contour\":{},\"index\":{},\"generation\":{},\"best\":{},\"mean\":{},\"median\":{},\"worst\":{},\"diversity\":{},\"elapsed\":{}}}",
+                    segment.contour,
+                    segment.index,
+                    generation,
+                    s.best,
+                    s.mean,
+                    s.median,
+                    s.worst,
+                    s.diversity,
+                    elapsed.as_secs_f64()
+                )
+            } else {
+                writeln!(
+                    self.file,
+                    "{},{},{},{},{},{},{},{},{}",
+                    segment.contour,
+                    segment.index,
+                    generation,
+                    s.best,
+                    s.mean,
+                    s.median,
+                    s.worst,
+                    s.diversity,
+                    elapsed.as_secs_f64()
+                )
+            }
+            .unwrap();
+        }
+    }
+}
+
+pub struct TikzOptions {
+    /* Centímetros por píxel */
+    pub scale: f64,
+    /* TikZ tiene el eje Y hacia arriba */
+    pub flip_y: bool,
+}
+
+pub fn export_tikz(contours: &[Vec<Bezier>], filename: PathBuf, options: &TikzOptions) {
+    let coordinate = |p: &Point| {
+        let y = if options.flip_y { 0.0 - p.y } else { p.y };
+        format!("({:.3},{:.3})", p.x * options.scale, y * options.scale)
+    };
+
+    let mut tikz = String::from(
+        "\\documentclass[tikz]{standalone}\n\\begin{document}\n\\begin{tikzpicture}\n",
+    );
+    for contour in contours {
+        tikz += "\\draw";
+        /* Una coordenada suelta mueve el lápiz si falta algún segmento */
+        for run in bezier::contours(contour) {
+            tikz += &format!(" {}", coordinate(&run[0].start));
+            for line in run.iter() {
+                tikz += &format!(
+                    " .. controls {} and {} .. {}",
+                    coordinate(&line.control1),
+                    coordinate(&line.control2),
+                    coordinate(&line.end)
+                );
+            }
+        }
+        tikz += ";\n";
+    }
+    tikz += "\\end{tikzpicture}\n\\end{document}\n";
+
+    let mut file = File::create(filename).unwrap();
+    file.write_all(tikz.as_bytes()).unwrap();
+}
+
+pub enum CodeTarget {
+    JavaScript,
+    Cairo,
+}
+
+pub fn export_code(contours: &[Vec<Bezier>], filename: PathBuf, target: CodeTarget) {
+    let mut code = match target {
+        CodeTarget::JavaScript => String::from("function draw(ctx) {\n    ctx.beginPath();\n"),
+        CodeTarget::Cairo => String::from("pub fn draw(cr: &cairo::Context) {\n"),
+    };
+    for run in contours
+        .iter()
+        .flat_map(|contour| bezier::contours(contour))
+    {
+        let start = run[0].start;
+        code += &match target {
+            CodeTarget::JavaScript => format!("    ctx.moveTo({:.3}, {:.3});\n", start.x, start.y),
+            CodeTarget::Cairo => format!("    cr.move_to({:.3}, {:.3});\n", start.x, start.y),
+        };
+        for line in run.iter() {
+            let method = match target {
+                CodeTarget::JavaScript => "ctx.bezierCurveTo",
+                CodeTarget::Cairo => "cr.curve_to",
+            };
+            code += &format!(
+                "    {}({:.3}, {:.3}, {:.3}, {:.3}, {:.3}, {:.3});\n",
+                method,
+                line.control1.x,
+                line.control1.y,
+                line.control2.x,
+                line.control2.y,
+                line.end.x,
+                line.end.y
+            );
+        }
+    }
+    code += match target {
+        CodeTarget::JavaScript => "    ctx.stroke();\n}\n",
+        CodeTarget::Cairo => "    cr.stroke().unwrap();\n}\n",
+    };
+
+    let mut file = File::create(filename).unwrap();
+    file.write_all(code.as_bytes()).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::import;
+
+    fn p(x: f64, y: f64) -> Point {
+        Point { x, y }
+    }
+
+    fn curve(start: Point, control1: Point, control2: Point, end: Point) -> Bezier {
+        Bezier {
+            start,
+            control1,
+            control2,
+            end,
+        }
+    }
+
+    #[test]
+    fn format_numbers() {
+        assert_eq!(format_number(0.5, 2), ".5");
+        assert_eq!(format_number(-0.5, 2), "-.5");
+        assert_eq!(format_number(1.0, 2), "1");
+        assert_eq!(format_number(100.0, 2), "100");
+        assert_eq!(format_number(1.1, 2), "1.1");
+        assert_eq!(format_number(-0.001, 2), "0");
+        assert_eq!(format_number(10.4, 0), "10");
+        assert_eq!(format_number(-2.25, 1), "-2.2");
+    }
+
+    #[test]
+    fn separators() {
+        let mut path = PathWriter::new(2);
+        path.command('M', &[1.0, 2.0]);
+        path.command('c', &[0.5, -0.5, 0.5, 0.5, 1.0, 0.0]);
+        path.command('c', &[0.25, 3.0, -1.0, 0.5, 2.0, 2.0]);
+        path.command('s', &[1.5, 0.5, 0.0, 0.0]);
+        path.command('M', &[5.0, 5.0]);
+        path.command('M', &[6.0, 6.0]);
+        path.command('z', &[]);
+        assert_eq!(
+            path.data,
+            "M1 2c.5-.5.5.5 1 0 .25 3-1 .5 2 2s1.5.5 0 0M5 5M6 6z"
+        );
+    }
+
+    /* Los extremos son esquinas, enteras, los controles no */
+    fn drawing() -> Vec<Vec<Bezier>> {
+        vec![
+            vec![
+                curve(
+                    p(10.0, 10.0),
+                    p(20.123, 5.456),
+                    p(33.3333, 4.0),
+                    p(40.0, 10.0),
+                ),
+                /* control1 es el reflejo del control2 anterior, se escribe con S */
+                curve(
+                    p(40.0, 10.0),
+                    p(46.6667, 16.0),
+                    p(35.01, 39.99),
+                    p(25.0, 40.0),
+                ),
+                curve(p(25.0, 40.0), p(17.77, 33.1), p(0.005, 20.0), p(10.0, 10.0)),
+            ],
+            vec![curve(
+                p(100.0, 100.0),
+                p(110.5, -1.25),
+                p(120.0, 99.999),
+                p(130.0, 100.0),
+            )],
+        ]
+    }
+
+    fn round_trip(relative: bool, precision: usize) {
+        let lines = drawing();
+        let filename = std::env::temp_dir().join(format!(
+            "mendel-round-trip-{}-{}-{}.svg",
+            std::process::id(),
+            relative,
+            precision
+        ));
+        let options = SvgOptions {
+            precision,
+            relative,
+            fill: None,
+            source: None,
+        };
+        export(&lines, filename.clone(), &options);
+        let svg = std::fs::read_to_string(&filename).unwrap();
+        let contours = import::import(filename.clone());
+        std::fs::remove_file(filename).unwrap();
+
+        let data: String = svg
+            .split(" d=\"")
+            .skip(1)
+            .map(|d| &d[..d.find('"').unwrap()])
+            .collect();
+        assert_eq!(data.matches(if relative { 's' } else { 'S' }).count(), 1);
+        assert_eq!(contours.len(), 2);
+        assert_eq!(contours[0].len(), 3);
+        assert_eq!(contours[1].len(), 1);
+        let tolerance = 0.5 * 10f64.powi(-(precision as i32)) + 1e-9;
+        for (imported, original) in contours.iter().flatten().zip(lines.iter().flatten()) {
+            for (a, b) in [
+                (imported.start, original.start),
+                (imported.control1, original.control1),
+                (imported.control2, original.control2),
+                (imported.end, original.end),
+            ] {
+                assert!(
+                    (a.x - b.x).abs() <= tolerance && (a.y - b.y).abs() <= tolerance,
+                    "({}, {}) != ({}, {})",
+                    a.x,
+                    a.y,
+                    b.x,
+                    b.y
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn round_trip_relative() {
+        round_trip(true, 2);
+        round_trip(true, 3);
+    }
+
+    #[test]
+    fn round_trip_absolute() {
+        round_trip(false, 2);
+        round_trip(false, 3);
+    }
+
+    #[test]
+    fn run_metadata() {
+        let config = GeneticConfig {
+            seed: 42,
+            selection: Selection::Tournament(5),
+            crossover: Crossover::SimulatedBinary(2.5),
+            mutation: Mutation::OneFifth,
+            fitness: Fitness::Gradient,
+            ..Default::default()
+        };
+        let run = run_attributes(&config, "cmaes");
+        for attribute in [
+            " optimizer=\"cmaes\"",
+            " seed=\"42\"",
+            " init=\"straight,previous\"",
+            " selection=\"tournament\" tournament-size=\"5\"",
+            " elitism=\"1\"",
+            " crossover=\"sbx\" sbx-eta=\"2.5\"",
+            " crossover-rate=\"0.9\"",
+            " mutation=\"one-fifth\"",
+            " mutation-scale=\"0.5\"",
+            " cmaes-population=\"20\"",
+            " fitness=\"gradient\"",
+            " threshold=\"200\"",
+            " invert=\"false\"",
+        ] {
+            assert!(run.contains(attribute), "{} not in {}", attribute, run);
+        }
+    }
+
+    fn result(contour: usize, index: usize, start: Point, end: Point) -> SegmentResult {
+        SegmentResult {
+            contour,
+            index,
+            curve: curve(start, start.middle(&end), start.middle(&end), end),
+            score: 0.0,
+            generations: 0,
+            converged: true,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn contours_by_result() {
+        let results = vec![
+            result(0, 0, p(0.0, 0.0), p(10.0, 0.0)),
+            result(0, 1, p(10.0, 0.0), p(10.0, 10.0)),
+            result(0, 2, p(10.0, 10.0), p(0.0, 0.0)),
+            /* Empieza donde acaba el anterior, pero es otro contorno */
+            result(1, 0, p(0.0, 0.0), p(-10.0, 0.0)),
+            result(1, 1, p(-10.0, 0.0), p(-10.0, -10.0)),
+            /* Falta el segmento 1 */
+            result(2, 0, p(50.0, 50.0), p(60.0, 50.0)),
+            result(2, 2, p(70.0, 50.0), p(80.0, 50.0)),
+        ];
+        let options = ExportOptions {
+            svg: SvgOptions {
+                precision: 2,
+                relative: false,
+                fill: None,
+                source: None,
+            },
+            hpgl: HpglOptions {
+                tolerance: 0.1,
+                units_per_mm: 40.0,
+                pen: PenMode::Contour,
+            },
+            tikz: TikzOptions {
+                scale: 1.0,
+                flip_y: false,
+            },
+        };
+        let export = |extension: &str| {
+            let filename = std::env::temp_dir().join(format!(
+                "mendel-contours-{}.{}",
+                std::process::id(),
+                extension
+            ));
+            export_file(&results, filename.clone(), &options);
+            let text = std::fs::read_to_string(&filename).unwrap();
+            std::fs::remove_file(filename).unwrap();
+            text
+        };
+
+        let svg = export("svg");
+        let paths: Vec<&str> = svg.split("<path d=\"").skip(1).collect();
+        assert_eq!(paths.len(), 3);
+        assert!(paths[0].starts_with("M0 0C") && paths[0].contains('Z'));
+        assert_eq!(paths[1].matches('M').count(), 1);
+        assert_eq!(paths[2].matches('M').count(), 2);
+        assert!(!paths[2].contains('Z'));
+
+        assert_eq!(export("json").matches("segments").count(), 3);
+        assert_eq!(export("hpgl").matches("SP").count(), 4);
+        assert_eq!(export("hpgl").matches("PU").count(), 5);
+        assert_eq!(export("tex").matches("\\draw").count(), 3);
+        assert_eq!(export("js").matches("moveTo").count(), 4);
+    }
+
+    /* Cuadrado en sentido horario con el eje Y hacia abajo, con lados rectos */
+    fn square(x: f64, y: f64, size: f64) -> Vec<Bezier> {
+        let corners = [
+            p(x, y),
+            p(x + size, y),
+            p(x + size, y + size),
+            p(x, y + size),
+        ];
+        (0..4)
+            .map(|i| {
+                let (a, b) = (corners[i], corners[(i + 1) % 4]);
+                let at = |t: f64| p(a.x + (b.x - a.x) * t, a.y + (b.y - a.y) * t);
+                curve(a, at(1.0 / 3.0), at(2.0 / 3.0), b)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn nonzero_holes() {
+        let outer = square(0.0, 0.0, 100.0);
+        let inner = square(40.0, 40.0, 30.0);
+        for outer in [outer.clone(), bezier::reverse(&outer)] {
+            for inner in [inner.clone(), bezier::reverse(&inner)] {
+                let contours = oriented(&[outer.clone(), inner]);
+                assert!((bezier::signed_area(&contours[0]) - 10000.0).abs() < 1e-6);
+                assert!((bezier::signed_area(&contours[1]) + 900.0).abs() < 1e-6);
+            }
+        }
+    }
+}
@@ -0,0 +1,471 @@
+/*
+ *  This file is part of Mendel Vectorizer.
+ *
+ *  Mendel Vectorizer is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  Mendel Vectorizer is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with Mendel Vectorizer.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use image::{GrayImage, Luma};
+use imageproc::distance_transform::euclidean_squared_distance_transform;
+
+use crate::bezier::{Bezier, Point};
+use crate::genetic::{self, Event, Fitness, GeneticConfig, Picture, SegmentResult};
+use crate::segment::Segment;
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/* Distancia máxima de una esquina al trazo */
+const SNAP_RADIUS: i64 = 3;
+/* Coste de pasar por un píxel junto al fondo, además de la longitud del paso */
+const CENTRE_WEIGHT: f64 = 4.0;
+const NEWTON_ITERATIONS: usize = 10;
+
+/* Distancia de los píxeles del trazo a la curva */
+#[derive(Copy, Clone)]
+pub struct FitError {
+    pub rms: f64,
+    pub max: f64,
+}
+
+/* Ajuste por mínimos cuadrados, None si se cancela */
+pub fn algorithm(
+    image: &Picture,
+    segment: Segment,
+    config: &GeneticConfig,
+    cancel: &AtomicBool,
+    progress: &dyn Fn(Event),
+) -> Option<SegmentResult> {
+    if cancel.load(Ordering::Relaxed) {
+        return None;
+    }
+    progress(Event::Started(segment));
+    let curve = fit(image, &segment, config);
+    let result = SegmentResult::new(image, segment.contour, segment.index, curve, 0, config);
+    progress(Event::Finished(result.clone()));
+    Some(result)
+}
+
+/* Curva que sigue el trazo entre las dos esquinas, recta si no se encuentra el trazo */
+pub fn fit(image: &GrayImage, segment: &Segment, config: &GeneticConfig) -> Bezier {
+    match trace(image, &segment.start, &segment.end, config) {
+        Some(points) => least_squares(&points),
+        None => least_squares(&[segment.start, segment.end]),
+    }
+}
+
+pub fn error(image: &GrayImage, curve: &Bezier, config: &GeneticConfig) -> Option<FitError> {
+    let points = trace(image, &curve.start, &curve.end, config)?;
+    let polyline = curve.flatten(0.1);
+    let distances: Vec<f64> = points
+        .iter()
+        .map(|point| {
+            polyline
+                .windows(2)
+                .map(|w| point.distance_to_segment(&w[0], &w[1]))
+                .fold(f64::INFINITY, f64::min)
+        })
+        .collect();
+    Some(FitError {
+        rms: (distances.iter().map(|d| d * d).sum::<f64>() / distances.len() as f64).sqrt(),
+        max: distances.iter().cloned().fold(0.0, f64::max),
+    })
+}
+
+fn on_stroke(image: &GrayImage, x: u32, y: u32, config: &GeneticConfig) -> bool {
+    match config.fitness {
+        Fitness::Stroke => genetic::is_dark(image, x, y, config),
+        Fitness::Boundary | Fitness::Gradient => genetic::on_boundary(image, x, y, config),
+    }
+}
+
+/* Camino por el centro del trazo entre las dos esquinas (Dijkstra). Los pasos junto al fondo
+ * cuestan más, si no el camino más corto se pega a un borde del trazo */
+pub fn trace(
+    image: &GrayImage,
+    start: &Point,
+    end: &Point,
+    config: &GeneticConfig,
+) -> Option<Vec<Point>> {
+    let margin = (start.distance(end) / 2.0).max(10.0);
+    let min_x = (start.x.min(end.x) - margin).max(0.0) as i64;
+    let min_y = (start.y.min(end.y) - margin).max(0.0) as i64;
+    let max_x = ((start.x.max(end.x) + margin) as i64).min(image.width() as i64 - 1);
+    let max_y = ((start.y.max(end.y) + margin) as i64).min(image.height() as i64 - 1);
+    if min_x > max_x || min_y > max_y {
+        return None;
+    }
+    let width = (max_x - min_x + 1) as usize;
+    let height = (max_y - min_y + 1) as usize;
+    let index = |x: i64, y: i64| (y - min_y) as usize * width + (x - min_x) as usize;
+    let inside: Vec<bool> = (0..width * height)
+        .map(|i| {
+            on_stroke(
+                image,
+                (min_x as usize + i % width) as u32,
+                (min_y as usize + i / width) as u32,
+                config,
+            )
+        })
+        .collect();
+    let stroke = |x: i64, y: i64| {
+        x >= min_x && x <= max_x && y >= min_y && y <= max_y && inside[index(x, y)]
+    };
+
+    /* Distancia al cuadrado de cada píxel del trazo al fondo más cercano */
+    let background = GrayImage::from_fn(width as u32, height as u32, |x, y| {
+        if inside[y as usize * width + x as usize] {
+            Luma([0])
+        } else {
+            Luma([255])
+        }
+    });
+    let depth = euclidean_squared_distance_transform(&background);
+
+    /* Píxel del trazo más cercano a la esquina */
+    let snap = |p: &Point| {
+        let (px, py) = (p.x.round() as i64, p.y.round() as i64);
+        (-SNAP_RADIUS..=SNAP_RADIUS)
+            .flat_map(|dy| (-SNAP_RADIUS..=SNAP_RADIUS).map(move |dx| (px + dx, py + dy)))
+            .filter(|&(x, y)| stroke(x, y))
+            .min_by_key(|&(x, y)| (x - px).pow(2) + (y - py).pow(2))
+    };
+    let from = snap(start)?;
+    let to = snap(end)?;
+
+    let mut cost = vec![f64::INFINITY; width * height];
+    let mut parent = vec![usize::MAX; width * height];
+    /* Los bits de un f64 positivo se ordenan igual que el número */
+    let mut heap = BinaryHeap::new();
+    cost[index(from.0, from.1)] = 0.0;
+    parent[index(from.0, from.1)] = index(from.0, from.1);
+    heap.push(Reverse((0f64.to_bits(), from.0, from.1)));
+    while let Some(Reverse((bits, x, y))) = heap.pop() {
+        let current = f64::from_bits(bits);
+        if (x, y) == to {
+            break;
+        }
+        if current > cost[index(x, y)] {
+            continue;
+        }
+        for (dx, dy) in [
+            (-1, -1),
+            (0, -1),
+            (1, -1),
+            (-1, 0),
+            (1, 0),
+            (-1, 1),
+            (0, 1),
+            (1, 1),
+        ] {
+            let (nx, ny) = (x + dx, y + dy);
+            if !stroke(nx, ny) {
+                continue;
+            }
+            let step = if dx != 0 && dy != 0 {
+                std::f64::consts::SQRT_2
+            } else {
+                1.0
+            };
+            let distance = depth.get_pixel((nx - min_x) as u32, (ny - min_y) as u32)[0];
+            let next = current + step * (1.0 + CENTRE_WEIGHT / distance);
+            if next < cost[index(nx, ny)] {
+                cost[index(nx, ny)] = next;
+                parent[index(nx, ny)] = index(x, y);
+                heap.push(Reverse((next.to_bits(), nx, ny)));
+            }
+        }
+    }
+    if parent[index(to.0, to.1)] == usize::MAX {
+        return None;
+    }
+
+    /* Centro del píxel, evaluate trunca las coordenadas */
+    let pixel = |i: usize| Point {
+        x: (min_x + (i % width) as i64) as f64 + 0.5,
+        y: (min_y + (i / width) as i64) as f64 + 0.5,
+    };
+    let mut path = Vec::new();
+    let mut i = index(to.0, to.1);
+    while parent[i] != i {
+        path.push(pixel(i));
+        i = parent[i];
+    }
+    path.push(pixel(i));
+    path.reverse();
+
+    /* Los extremos son las esquinas exactas */
+    if path.len() == 1 {
+        path.push(path[0]);
+    }
+    let last = path.len() - 1;
+    path[0] = *start;
+    path[last] = *end;
+    Some(path)
+}
+
+fn sub(a: &Point, b: &Point) -> Point {
+    Point {
+        x: a.x - b.x,
+        y: a.y - b.y,
+    }
+}
+
+fn dot(a: &Point, b: &Point) -> f64 {
+    a.x * b.x + a.y * b.y
+}
+
+/* Curva cúbica por mínimos cuadrados con los extremos fijos, como el algoritmo de Schneider
+ * ("An Algorithm for Automatically Fitting Digitized Curves", Graphics Gems) pero con los
+ * puntos de control libres: cada segmento se ajusta por separado, no hace falta continuidad G1 */
+pub fn least_squares(points: &[Point]) -> Bezier {
+    /* Parametrización por longitud de cuerda */
+    let mut lengths = vec![0.0];
+    for w in points.windows(2) {
+        lengths.push(lengths[lengths.len() - 1] + w[0].distance(&w[1]));
+    }
+    let total = lengths[lengths.len() - 1];
+    let mut u: Vec<f64> = if total > 0.0 {
+        lengths.iter().map(|l| l / total).collect()
+    } else {
+        (0..points.len())
+            .map(|i| i as f64 / (points.len() - 1).max(1) as f64)
+            .collect()
+    };
+
+    let mut best = generate(points, &u);
+    let mut best_error = squared_error(points, &u, &best);
+    for _ in 0..NEWTON_ITERATIONS {
+        u = reparameterize(points, &u, &best);
+        let curve = generate(points, &u);
+        let error = squared_error(points, &u, &curve);
+        if error < best_error {
+            best = curve;
+            best_error = error;
+        }
+    }
+    best
+}
+
+fn bernstein(t: f64) -> [f64; 4] {
+    let s = 1.0 - t;
+    [s * s * s, 3.0 * t * s * s, 3.0 * t * t * s, t * t * t]
+}
+
+fn point_at(curve: &Bezier, t: f64) -> Point {
+    let b = bernstein(t);
+    Point {
+        x: b[0] * curve.start.x
+            + b[1] * curve.control1.x
+            + b[2] * curve.control2.x
+            + b[3] * curve.end.x,
+        y: b[0] * curve.start.y
+            + b[1] * curve.control1.y
+            + b[2] * curve.control2.y
+            + b[3] * curve.end.y,
+    }
+}
+
+/* Puntos de control que minimizan el error para los parámetros u: un sistema de 2x2,
+ * el mismo para x y para y */
+fn generate(points: &[Point], u: &[f64]) -> Bezier {
+    let first = points[0];
+    let last = points[points.len() - 1];
+    let (mut c11, mut c12, mut c22) = (0.0, 0.0, 0.0);
+    let (mut x1, mut x2) = (Point { x: 0.0, y: 0.0 }, Point { x: 0.0, y: 0.0 });
+    for (point, t) in points.iter().zip(u) {
+        let b = bernstein(*t);
+        let rest = Point {
+            x: point.x - first.x * b[0] - last.x * b[3],
+            y: point.y - first.y * b[0] - last.y * b[3],
+        };
+        c11 += b[1] * b[1];
+        c12 += b[1] * b[2];
+        c22 += b[2] * b[2];
+        x1.x += b[1] * rest.x;
+        x1.y += b[1] * rest.y;
+        x2.x += b[2] * rest.x;
+        x2.y += b[2] * rest.y;
+    }
+
+    let det = c11 * c22 - c12 * c12;
+    /* Con menos de dos puntos interiores no hay solución, se usa la recta */
+    if det.abs() <= 1e-12 * (c11 * c22).max(1e-12) {
+        return Bezier {
+            start: first,
+            control1: Point {
+                x: first.x + (last.x - first.x) / 3.0,
+                y: first.y + (last.y - first.y) / 3.0,
+            },
+            control2: Point {
+                x: first.x + 2.0 * (last.x - first.x) / 3.0,
+                y: first.y + 2.0 * (last.y - first.y) / 3.0,
+            },
+            end: last,
+        };
+    }
+    Bezier {
+        start: first,
+        control1: Point {
+            x: (x1.x * c22 - x2.x * c12) / det,
+            y: (x1.y * c22 - x2.y * c12) / det,
+        },
+        control2: Point {
+            x: (c11 * x2.x - c12 * x1.x) / det,
+            y: (c11 * x2.y - c12 * x1.y) / det,
+        },
+        end: last,
+    }
+}
+
+fn squared_error(points: &[Point], u: &[f64], curve: &Bezier) -> f64 {
+    points
+        .iter()
+        .zip(u)
+        .map(|(point, t)| {
+            let d = sub(&point_at(curve, *t), point);
+            dot(&d, &d)
+        })
+        .sum()
+}
+
+/* Una iteración de Newton-Raphson para acercar cada parámetro al punto más cercano de la curva */
+fn reparameterize(points: &[Point], u: &[f64], curve: &Bezier) -> Vec<f64> {
+    let p = [curve.start, curve.control1, curve.control2, curve.end];
+    let d1: Vec<Point> = (0..3)
+        .map(|i| Point {
+            x: 3.0 * (p[i + 1].x - p[i].x),
+            y: 3.0 * (p[i + 1].y - p[i].y),
+        })
+        .collect();
+    let d2: Vec<Point> = (0..2)
+        .map(|i| Point {
+            x: 2.0 * (d1[i + 1].x - d1[i].x),
+            y: 2.0 * (d1[i + 1].y - d1[i].y),
+        })
+        .collect();
+    points
+        .iter()
+        .zip(u)
+        .map(|(point, &t)| {
+            let s = 1.0 - t;
+            let q = sub(&point_at(curve, t), point);
+            let q1 = Point {
+                x: d1[0].x * s * s + 2.0 * d1[1].x * s * t + d1[2].x * t * t,
+                y: d1[0].y * s * s + 2.0 * d1[1].y * s * t + d1[2].y * t * t,
+            };
+            let q2 = Point {
+                x: d2[0].x * s + d2[1].x * t,
+                y: d2[0].y * s + d2[1].y * t,
+            };
+            let denominator = dot(&q1, &q1) + dot(&q, &q2);
+            if denominator.abs() < 1e-12 {
+                t
+            } else {
+                (t - dot(&q, &q1) / denominator).clamp(0.0, 1.0)
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn p(x: f64, y: f64) -> Point {
+        Point { x, y }
+    }
+
+    fn arch() -> Bezier {
+        Bezier {
+            start: p(10.0, 60.0),
+            control1: p(30.0, 0.0),
+            control2: p(80.0, 0.0),
+            end: p(100.0, 60.0),
+        }
+    }
+
+    /* Trazo de 3 píxeles de ancho a lo largo de la curva */
+    fn stroke(curve: &Bezier) -> GrayImage {
+        let mut image = GrayImage::from_pixel(110, 70, Luma([255]));
+        for i in 0..=1000 {
+            let point = point_at(curve, i as f64 / 1000.0);
+            for dy in -1..=1 {
+                for dx in -1..=1 {
+                    let (x, y) = (point.x as i64 + dx, point.y as i64 + dy);
+                    if x >= 0 && y >= 0 && x < 110 && y < 70 {
+                        image.put_pixel(x as u32, y as u32, Luma([0]));
+                    }
+                }
+            }
+        }
+        image
+    }
+
+    fn close(a: &Point, b: &Point, tolerance: f64) -> bool {
+        a.distance(b) <= tolerance
+    }
+
+    #[test]
+    fn exact_samples() {
+        let curve = arch();
+        let points: Vec<Point> = (0..=50)
+            .map(|i| point_at(&curve, i as f64 / 50.0))
+            .collect();
+        let fitted = least_squares(&points);
+        assert!(close(&fitted.control1, &curve.control1, 1.0));
+        assert!(close(&fitted.control2, &curve.control2, 1.0));
+    }
+
+    #[test]
+    fn too_few_points() {
+        let fitted = least_squares(&[p(0.0, 0.0), p(30.0, 0.0)]);
+        assert!(fitted.control1 == p(10.0, 0.0) && fitted.control2 == p(20.0, 0.0));
+    }
+
+    #[test]
+    fn centreline() {
+        let curve = arch();
+        let config = GeneticConfig::default();
+        let path = trace(&stroke(&curve), &curve.start, &curve.end, &config).unwrap();
+        /* Cada punto del camino cerca de la curva, no en un borde del trazo */
+        let polyline = curve.flatten(0.1);
+        for point in &path {
+            let distance = polyline
+                .windows(2)
+                .map(|w| point.distance_to_segment(&w[0], &w[1]))
+                .fold(f64::INFINITY, f64::min);
+            assert!(distance < 1.0, "({}, {}) at {}", point.x, point.y, distance);
+        }
+    }
+
+    #[test]
+    fn synthetic_stroke() {
+        let curve = arch();
+        let config = GeneticConfig::default();
+        let image = Picture::new(stroke(&curve), &config);
+        let segment = Segment {
+            contour: 0,
+            index: 0,
+            start: curve.start,
+            end: curve.end,
+        };
+        let result = algorithm(&image, segment, &config, &AtomicBool::new(false), &|_| {}).unwrap();
+        assert!(close(&result.curve.control1, &curve.control1, 3.0));
+        assert!(close(&result.curve.control2, &curve.control2, 3.0));
+        assert!(result.score >= config.target_score);
+        let error = result.error.unwrap();
+        assert!(error.rms < 1.0 && error.max < 2.0);
+    }
+}
@@ -15,161 +15,857 @@
  *  along with Mendel Vectorizer.  If not, see <https://www.gnu.org/licenses/>.
 */
 
-use image::imageops::colorops::grayscale;
 use image::GenericImageView;
 use image::GrayImage;
-use imageproc::corners::Corner;
+use image::ImageBuffer;
+use image::Luma;
+use imageproc::contrast::otsu_level;
+use imageproc::gradients::{horizontal_sobel, vertical_sobel};
 
 use crate::bezier::{Bezier, Point};
+use crate::fit::{self, FitError};
+use crate::segment::Segment;
 
 use rand_distr::Normal;
+use rand::distributions::WeightedIndex;
 use rand::prelude::*;
 use rand::Rng;
 
-use std::sync::mpsc::Sender;
+use rayon::prelude::*;
 
-const GOOD_ONES: usize = 500;
+use std::fmt;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
-pub fn algorithm(image: String, corners: &[Corner], tx: &Sender<Bezier>) {
-    /* Abrir imagen */
-    let image = image::open(image).unwrap(); /* O(1) */
-    let image = grayscale(&image); /* O(n*m) n (ancho) m(alto) */
+#[derive(Copy, Clone)]
+pub enum Fitness {
+    /* Las curvas deben pasar por píxeles oscuros */
+    Stroke,
+    /* Las curvas deben seguir el borde entre zonas oscuras y claras */
+    Boundary,
+    /* Las curvas deben seguir bordes (gradiente de Sobel) con su misma dirección */
+    Gradient,
+}
 
-    /* Para cada punto ejecutamos el algoritmo genético con el siguiente punto */
-    for i in 0..corners.len() - 1 {
-        let start_corner = corners[i];
-        let start = Point {
-            x: start_corner.x as f64,
-            y: start_corner.y as f64,
-        };
-        let end_corner = if corners.len() == i + 1 {
-            corners[0]
-        } else {
-            corners[i + 1]
-        };
-        let end = Point {
-            x: end_corner.x as f64,
-            y: end_corner.y as f64,
-        };
+/* Cómo se eligen los individuos que sobreviven */
+#[derive(Copy, Clone)]
+pub enum Selection {
+    /* Los mejores */
+    Truncation,
+    /* El mejor de k individuos al azar */
+    Tournament(usize),
+    /* Probabilidad proporcional al fitness */
+    Roulette,
+    /* Probabilidad proporcional a la posición en la clasificación */
+    Rank,
+}
 
-        // INITIAL POPULATION
-        let mut population = Vec::new();
-        let mut rng = thread_rng();
-        let distancia = start.distance(&end);
-        for _ in 0..1000 {
-            let xrand: f64 = rng.gen_range(-distancia..distancia);
-            let yrand: f64 = rng.gen_range(-distancia..distancia);
-            let mut control1 = start.middle(&end);
-            control1.x += xrand;
-            control1.y += yrand;
-            let mut control2 = start.middle(&end);
-            control2.x += xrand;
-            control2.y += yrand;
-            population.push(Bezier {
-                start,
-                end,
-                control1,
-                control2,
-            });
-        }
-        // SELECTION
-        let mut population = natural_selection(&image, population);
-
-        while evaluate(&image, &population[0]) < 80.0 {
-            println!("BEST: {}", evaluate(&image, &population[0]));
-            // CROSSOVER
-            // Blend o Linear (Blend) https://engineering.purdue.edu/~sudhoff/ee630/Lecture04.pdf
-            let mut i: usize = 0;
-            let mut babies = Vec::new();
-            while i < GOOD_ONES {
-                // PROBABILIDAD CROSSOVER 100%, pero se mantienen los anteriores
-                // 250 extra
-                let line1 = &population[i];
-                let line2 = &population[i + 1];
-
-                let min_x = line1.control1.x.min(line2.control1.x);
-                let max_x = line1.control1.x.max(line2.control1.x);
-                let min_y = line1.control1.y.min(line2.control1.y);
-                let max_y = line1.control1.y.max(line2.control1.y);
-                let control1 = Point {
-                    x: rng.gen_range(min_x..max_x),
-                    y: rng.gen_range(min_y..max_y),
-                };
-
-                let min_x = line1.control2.x.min(line2.control2.x);
-                let max_x = line1.control2.x.max(line2.control2.x);
-                let min_y = line1.control2.y.min(line2.control2.y);
-                let max_y = line1.control2.y.max(line2.control2.y);
-                let control2 = Point {
-                    x: rng.gen_range(min_x..max_x),
-                    y: rng.gen_range(min_y..max_y),
-                };
-
-                babies.push(Bezier {
-                    start,
-                    end,
-                    control1,
-                    control2,
-                });
+/* Cómo se combinan los puntos de control de dos padres */
+#[derive(Copy, Clone)]
+pub enum Crossover {
+    /* BLX-α: uniforme en el intervalo de los padres ampliado α veces su distancia */
+    Blend(f64),
+    /* SBX: simula el cruce de un bit en binario, con índice de distribución η */
+    SimulatedBinary(f64),
+    /* Media ponderada de los padres, con el mismo peso para todos los genes */
+    Arithmetic,
+}
+
+/* Cómo cambia la desviación de la mutación a lo largo de las generaciones */
+#[derive(Copy, Clone)]
+pub enum Mutation {
+    Fixed,
+    /* Se reduce linealmente hasta la última generación */
+    Schedule,
+    /* Regla de 1/5 de Rechenberg: crece si más de 1/5 de las mutaciones mejoran al individuo */
+    OneFifth,
+}
+
+/* Individuos de la población inicial que no son aleatorios */
+#[derive(Copy, Clone)]
+pub struct Seeding {
+    /* Recta, con los puntos de control a 1/3 y 2/3 */
+    pub straight: bool,
+    /* Una cuarta parte de la población con los puntos de control en píxeles oscuros cercanos */
+    pub dark: bool,
+    /* La mejor curva de la ejecución anterior, si no convergió */
+    pub previous: bool,
+    /* Ajuste por mínimos cuadrados al trazo */
+    pub fit: bool,
+}
+
+#[derive(Copy, Clone)]
+pub struct GeneticConfig {
+    pub seed: u64,
+    pub population: usize,
+    pub seeding: Seeding,
+    /* Individuos que sobreviven en cada generación */
+    pub good_ones: usize,
+    pub selection: Selection,
+    /* Los mejores individuos sobreviven siempre */
+    pub elitism: usize,
+    pub crossover: Crossover,
+    /* Probabilidad de cruzar a los padres, si no el hijo es una copia del primero */
+    pub crossover_rate: f64,
+    pub mutation_rate: f64,
+    pub mutation: Mutation,
+    /* Desviación inicial de la mutación, relativa a la distancia entre esquinas */
+    pub mutation_scale: f64,
+    /* Curvas por generación de CMA-ES */
+    pub cmaes_population: usize,
+    pub target_score: f64,
+    pub max_generations: usize,
+    pub fitness: Fitness,
+    /* Los píxeles por debajo del umbral son oscuros */
+    pub threshold: u8,
+    /* Dibujos claros sobre fondo oscuro */
+    pub invert: bool,
+    /* Evalúa la población de cada generación en varios hilos */
+    pub parallel_evaluation: bool,
+    /* Cada cuántas generaciones se envía una muestra de la población, 0 nunca */
+    pub snapshot_every: usize,
+}
+
+impl Default for GeneticConfig {
+    fn default() -> GeneticConfig {
+        GeneticConfig {
+            seed: 0,
+            population: 1000,
+            seeding: Seeding {
+                straight: true,
+                dark: false,
+                previous: true,
+                fit: false,
+            },
+            good_ones: 500,
+            selection: Selection::Truncation,
+            elitism: 1,
+            crossover: Crossover::Blend(0.5),
+            crossover_rate: 0.9,
+            mutation_rate: 0.10,
+            mutation: Mutation::Fixed,
+            mutation_scale: 0.5,
+            cmaes_population: 20,
+            target_score: 80.0,
+            max_generations: 1000,
+            fitness: Fitness::Stroke,
+            threshold: 200,
+            invert: false,
+            parallel_evaluation: false,
+            snapshot_every: 0,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SegmentResult {
+    /* Posición del segmento: contorno y orden dentro del contorno */
+    pub contour: usize,
+    pub index: usize,
+    pub curve: Bezier,
+    pub score: f64,
+    pub generations: usize,
+    pub converged: bool,
+    /* Distancia al trazo, None si no se encuentra el trazo entre las esquinas */
+    pub error: Option<FitError>,
+}
+
+impl SegmentResult {
+    pub fn new(
+        image: &Picture,
+        contour: usize,
+        index: usize,
+        curve: Bezier,
+        generations: usize,
+        config: &GeneticConfig,
+    ) -> SegmentResult {
+        let score = evaluate(image, &curve, config);
+        let error = fit::error(image, &curve, config);
+        SegmentResult {
+            contour,
+            index,
+            curve,
+            score,
+            generations,
+            converged: score >= config.target_score,
+            error,
+        }
+    }
+}
+
+/* Población de una generación, antes de descartar los peores */
+#[derive(Copy, Clone)]
+pub struct Statistics {
+    pub best: f64,
+    pub mean: f64,
+    pub median: f64,
+    pub worst: f64,
+    /* Distancia media de los puntos de control a su centro */
+    pub diversity: f64,
+}
 
-                i += 2;
+/* Eventos de progreso del algoritmo */
+pub enum Event {
+    Started(Segment),
+    Generation {
+        segment: Segment,
+        generation: usize,
+        statistics: Statistics,
+        /* Tiempo desde que empezó el segmento */
+        elapsed: Duration,
+    },
+    /* Mejor curva y una muestra de la población */
+    Snapshot {
+        segment: Segment,
+        generation: usize,
+        best: Bezier,
+        sample: Vec<Bezier>,
+    },
+    /* Ha convergido o se ha rendido, según result.converged */
+    Finished(SegmentResult),
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Event::Started(segment) => {
+                write!(f, "Segment {}:{} started", segment.contour, segment.index)
+            }
+            Event::Generation {
+                segment,
+                generation,
+                statistics,
+                ..
+            } => write!(
+                f,
+                "Segment {}:{} generation {}: best {:.2} mean {:.2} median {:.2} worst {:.2}",
+                segment.contour,
+                segment.index,
+                generation,
+                statistics.best,
+                statistics.mean,
+                statistics.median,
+                statistics.worst
+            ),
+            Event::Snapshot {
+                segment,
+                generation,
+                sample,
+                ..
+            } => write!(
+                f,
+                "Segment {}:{} generation {}: snapshot of {} curves",
+                segment.contour,
+                segment.index,
+                generation,
+                sample.len()
+            ),
+            Event::Finished(result) => {
+                write!(
+                    f,
+                    "Segment {}:{} {} after {} generations: {:.2}",
+                    result.contour,
+                    result.index,
+                    if result.converged {
+                        "converged"
+                    } else {
+                        "gave up"
+                    },
+                    result.generations,
+                    result.score
+                )?;
+                match result.error {
+                    Some(error) => {
+                        write!(f, ", error {:.2} px (max {:.2} px)", error.rms, error.max)
+                    }
+                    None => Ok(()),
+                }
             }
-            population.append(&mut babies);
-
-            // MUTATION
-            // TASA DE MUTACION DEL 10%
-            population = population
-                .into_iter()
-                .map(|mut line| {
-                    if rng.gen::<f64>() < 0.10 {
-                        let normal = Normal::new(0.0, distancia / 2.0).unwrap();
-                        let mutation_where: u32 = rng.gen_range(1..5);
-                        // Solo muta un gen, respecto a una Normal
-                        match mutation_where {
-                            1 => line.control1.x += rng.sample(normal),
-                            2 => line.control1.y += rng.sample(normal),
-                            3 => line.control2.x += rng.sample(normal),
-                            4 => line.control2.y += rng.sample(normal),
-                            _ => (),
+        }
+    }
+}
+
+/* Cada segmento tiene su propia semilla, así no depende del hilo que lo ejecute */
+pub fn segment_seed(seed: u64, start: &Point, end: &Point) -> u64 {
+    seed ^ ((start.x as u64) << 48)
+        ^ ((start.y as u64) << 32)
+        ^ ((end.x as u64) << 16)
+        ^ (end.y as u64)
+}
+
+/* Algoritmo genético para el segmento entre dos esquinas, None si se cancela.
+ * previous es el resultado de una ejecución anterior del mismo segmento */
+pub fn algorithm(
+    image: &Picture,
+    segment: Segment,
+    previous: Option<Bezier>,
+    config: &GeneticConfig,
+    cancel: &AtomicBool,
+    progress: &dyn Fn(Event),
+) -> Option<SegmentResult> {
+    if cancel.load(Ordering::Relaxed) {
+        return None;
+    }
+    progress(Event::Started(segment));
+    let started = Instant::now();
+    let Segment { start, end, .. } = segment;
+
+    // INITIAL POPULATION
+    let mut rng = StdRng::seed_from_u64(segment_seed(config.seed, &start, &end));
+    let distancia = start.distance(&end);
+    let population = initial_population(image, &segment, previous, config, &mut rng);
+    // SELECTION
+    let (mut population, mut statistics) = natural_selection(image, population, config, &mut rng);
+    progress(Event::Generation {
+        segment,
+        generation: 0,
+        statistics,
+        elapsed: started.elapsed(),
+    });
+
+    let mut sigma = distancia * config.mutation_scale;
+    let one_fifth = matches!(config.mutation, Mutation::OneFifth);
+    let mut mutations = 0;
+    let mut successes = 0;
+    let mut generations = 0;
+    while statistics.best < config.target_score && generations < config.max_generations {
+        if cancel.load(Ordering::Relaxed) {
+            return None;
+        }
+        generations += 1;
+        // CROSSOVER
+        // Se mantienen los anteriores y se añaden good_ones / 2 hijos de padres al azar
+        let mut babies = Vec::new();
+        for _ in 0..population.len() / 2 {
+            let line1 = population.choose(&mut rng).unwrap();
+            let line2 = population.choose(&mut rng).unwrap();
+            if rng.gen::<f64>() < config.crossover_rate {
+                babies.push(crossover(line1, line2, config, &mut rng));
+            } else {
+                babies.push(line1.clone());
+            }
+        }
+        population.append(&mut babies);
+
+        // MUTATION
+        // TASA DE MUTACION DEL 10% POR DEFECTO
+        if let Mutation::Schedule = config.mutation {
+            let remaining = 1.0 - generations as f64 / config.max_generations as f64;
+            sigma = (distancia * config.mutation_scale * remaining).max(MIN_SIGMA);
+        }
+        let normal = Normal::new(0.0, sigma).unwrap();
+        population = population
+            .into_iter()
+            .enumerate()
+            .map(|(i, mut line)| {
+                /* Los mejores no mutan, si no el elitismo no serviría de nada */
+                if i >= config.elitism && rng.gen::<f64>() < config.mutation_rate {
+                    /* Para la regla de 1/5 se compara el mutante con el individuo sin mutar */
+                    let parent = one_fifth.then(|| evaluate(image, &line, config));
+                    let mutation_where: u32 = rng.gen_range(1..5);
+                    // Solo muta un gen, respecto a una Normal
+                    match mutation_where {
+                        1 => line.control1.x += rng.sample(normal),
+                        2 => line.control1.y += rng.sample(normal),
+                        3 => line.control2.x += rng.sample(normal),
+                        4 => line.control2.y += rng.sample(normal),
+                        _ => (),
+                    }
+                    if let Some(parent) = parent {
+                        mutations += 1;
+                        if evaluate(image, &line, config) > parent {
+                            successes += 1;
                         }
                     }
-                    line
-                })
-                .collect();
+                }
+                line
+            })
+            .collect();
 
-            // VOLVER A EVALUAR
-            population = natural_selection(&image, population);
+        // VOLVER A EVALUAR
+        (population, statistics) = natural_selection(image, population, config, &mut rng);
+        if one_fifth && generations % ONE_FIFTH_WINDOW == 0 {
+            if successes * 5 > mutations {
+                sigma /= ONE_FIFTH_FACTOR;
+            } else if successes * 5 < mutations {
+                sigma = (sigma * ONE_FIFTH_FACTOR).max(MIN_SIGMA);
+            }
+            mutations = 0;
+            successes = 0;
+        }
+        progress(Event::Generation {
+            segment,
+            generation: generations,
+            statistics,
+            elapsed: started.elapsed(),
+        });
+        if config.snapshot_every > 0 && generations % config.snapshot_every == 0 {
+            progress(snapshot(segment, generations, &population));
         }
-        println!("Correct: {}", evaluate(&image, &population[0]));
-        tx.send(population[0].clone()).unwrap();
     }
+    let result = SegmentResult::new(
+        image,
+        segment.contour,
+        segment.index,
+        population[0].clone(),
+        generations,
+        config,
+    );
+    progress(Event::Finished(result.clone()));
+    Some(result)
 }
 
-pub fn natural_selection(image: &GrayImage, mut population: Vec<Bezier>) -> Vec<Bezier> {
-    population.sort_by(|a, b| {
-        let a = evaluate(&image, &a);
-        let b = evaluate(&image, &b);
-        b.partial_cmp(&a).unwrap()
-    });
+pub fn initial_population(
+    image: &GrayImage,
+    segment: &Segment,
+    previous: Option<Bezier>,
+    config: &GeneticConfig,
+    rng: &mut StdRng,
+) -> Vec<Bezier> {
+    let Segment { start, end, .. } = *segment;
+    let curve = |control1, control2| Bezier {
+        start,
+        end,
+        control1,
+        control2,
+    };
+    let mut population = Vec::new();
+    if config.seeding.straight {
+        let third = |t: f64| Point {
+            x: start.x + (end.x - start.x) * t,
+            y: start.y + (end.y - start.y) * t,
+        };
+        population.push(curve(third(1.0 / 3.0), third(2.0 / 3.0)));
+    }
+    if config.seeding.previous {
+        population.extend(previous);
+    }
+    if config.seeding.fit {
+        population.push(fit::fit(image, segment, config));
+    }
+
+    /* Cada punto de control por separado, alrededor del punto medio */
+    let distancia = start.distance(&end);
+    let middle = start.middle(&end);
+    let random = |rng: &mut StdRng| Point {
+        x: middle.x + rng.gen_range(-distancia..=distancia),
+        y: middle.y + rng.gen_range(-distancia..=distancia),
+    };
+    if config.seeding.dark {
+        for _ in 0..config.population / 4 {
+            let control1 = dark_pixel(image, segment, config, rng).unwrap_or_else(|| random(rng));
+            let control2 = dark_pixel(image, segment, config, rng).unwrap_or_else(|| random(rng));
+            population.push(curve(control1, control2));
+        }
+    }
+    while population.len() < config.population {
+        let control1 = random(rng);
+        let control2 = random(rng);
+        population.push(curve(control1, control2));
+    }
+    population
+}
+
+const DARK_PIXEL_TRIES: usize = 100;
 
-    population.into_iter().take(GOOD_ONES).collect()
+/* Píxel oscuro al azar cerca del segmento, como mucho a media distancia entre esquinas */
+fn dark_pixel(
+    image: &GrayImage,
+    segment: &Segment,
+    config: &GeneticConfig,
+    rng: &mut StdRng,
+) -> Option<Point> {
+    let (start, end) = (&segment.start, &segment.end);
+    let margin = (start.distance(end) / 2.0).max(1.0);
+    let max_x = (image.width() - 1) as f64;
+    let max_y = (image.height() - 1) as f64;
+    let min_x = (start.x.min(end.x) - margin).clamp(0.0, max_x);
+    let min_y = (start.y.min(end.y) - margin).clamp(0.0, max_y);
+    let max_x = (start.x.max(end.x) + margin).clamp(0.0, max_x);
+    let max_y = (start.y.max(end.y) + margin).clamp(0.0, max_y);
+    for _ in 0..DARK_PIXEL_TRIES {
+        let x = rng.gen_range(min_x..=max_x).round();
+        let y = rng.gen_range(min_y..=max_y).round();
+        if is_dark(image, x as u32, y as u32, config) {
+            return Some(Point { x, y });
+        }
+    }
+    None
 }
 
-pub fn evaluate(image: &GrayImage, line: &Bezier) -> f64 {
-    let mut eval = 0.0;
-    for point in line.iter() {
-        let x = point.x as u32;
-        let y = point.y as u32;
-        if image.in_bounds(x, y) {
-            let pixel = image.get_pixel(x, y);
-            if pixel[0] < 200 {
-                eval += 1.0;
+// Blend o Linear (Blend) https://engineering.purdue.edu/~sudhoff/ee630/Lecture04.pdf
+fn crossover(line1: &Bezier, line2: &Bezier, config: &GeneticConfig, rng: &mut StdRng) -> Bezier {
+    /* Peso del primer padre en el cruce aritmético */
+    let lambda: f64 = rng.gen();
+    let mut gene = |a: f64, b: f64| match config.crossover {
+        Crossover::Blend(alpha) => {
+            let extension = alpha * (a - b).abs();
+            /* Intervalo cerrado, así funciona con padres iguales */
+            rng.gen_range(a.min(b) - extension..=a.max(b) + extension)
+        }
+        Crossover::SimulatedBinary(eta) => {
+            let u: f64 = rng.gen();
+            let beta = if u <= 0.5 {
+                (2.0 * u).powf(1.0 / (eta + 1.0))
             } else {
-                eval -= 100.0;
-            }
+                (1.0 / (2.0 * (1.0 - u))).powf(1.0 / (eta + 1.0))
+            };
+            0.5 * ((1.0 + beta) * a + (1.0 - beta) * b)
+        }
+        Crossover::Arithmetic => lambda * a + (1.0 - lambda) * b,
+    };
+    Bezier {
+        start: line1.start,
+        end: line1.end,
+        control1: Point {
+            x: gene(line1.control1.x, line2.control1.x),
+            y: gene(line1.control1.y, line2.control1.y),
+        },
+        control2: Point {
+            x: gene(line1.control2.x, line2.control2.x),
+            y: gene(line1.control2.y, line2.control2.y),
+        },
+    }
+}
+
+pub fn natural_selection(
+    image: &Picture,
+    population: Vec<Bezier>,
+    config: &GeneticConfig,
+    rng: &mut StdRng,
+) -> (Vec<Bezier>, Statistics) {
+    let scores: Vec<f64> = if config.parallel_evaluation {
+        population
+            .par_iter()
+            .map(|line| evaluate(image, line, config))
+            .collect()
+    } else {
+        population
+            .iter()
+            .map(|line| evaluate(image, line, config))
+            .collect()
+    };
+    let mut population: Vec<(f64, Bezier)> = scores.into_iter().zip(population).collect();
+    population.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
+    let statistics = statistics(&population);
+
+    /* Los supervivientes quedan también ordenados de mejor a peor */
+    let mut survivors = select(&population, config, rng);
+    survivors.sort_unstable();
+    let population = survivors
+        .into_iter()
+        .map(|i| population[i].1.clone())
+        .collect();
+    (population, statistics)
+}
+
+/* Índices de los supervivientes, la población tiene que estar ordenada de mejor a peor */
+fn select(population: &[(f64, Bezier)], config: &GeneticConfig, rng: &mut StdRng) -> Vec<usize> {
+    let n = population.len();
+    let good_ones = config.good_ones.min(n);
+    let elite = config.elitism.min(good_ones);
+    let mut survivors: Vec<usize> = (0..elite).collect();
+    let rest = good_ones - elite;
+    match config.selection {
+        Selection::Truncation => survivors.extend(elite..good_ones),
+        /* Como están ordenados, el mejor del torneo es el de menor índice */
+        Selection::Tournament(k) => survivors
+            .extend((0..rest).map(|_| (0..k.max(1)).map(|_| rng.gen_range(0..n)).min().unwrap())),
+        Selection::Roulette => {
+            /* El fitness puede ser negativo, se desplaza para que el peor tenga peso 1 */
+            let worst = population[n - 1].0;
+            let weights =
+                WeightedIndex::new(population.iter().map(|(score, _)| score - worst + 1.0))
+                    .unwrap();
+            survivors.extend((0..rest).map(|_| weights.sample(rng)));
+        }
+        Selection::Rank => {
+            let weights = WeightedIndex::new((0..n).map(|i| n - i)).unwrap();
+            survivors.extend((0..rest).map(|_| weights.sample(rng)));
+        }
+    }
+    survivors
+}
+
+const SNAPSHOT_SAMPLE: usize = 20;
+/* Desviación mínima de la mutación, en píxeles */
+const MIN_SIGMA: f64 = 0.5;
+/* Generaciones entre cada ajuste de la regla de 1/5 y cuánto se ajusta */
+const ONE_FIFTH_WINDOW: usize = 10;
+const ONE_FIFTH_FACTOR: f64 = 0.82;
+
+/* La población tiene que estar ordenada de mejor a peor */
+pub fn snapshot(segment: Segment, generation: usize, population: &[Bezier]) -> Event {
+    let step = (population.len() / SNAPSHOT_SAMPLE).max(1);
+    Event::Snapshot {
+        segment,
+        generation,
+        best: population[0].clone(),
+        sample: population
+            .iter()
+            .step_by(step)
+            .take(SNAPSHOT_SAMPLE)
+            .cloned()
+            .collect(),
+    }
+}
+
+/* La población tiene que estar ordenada de mejor a peor */
+pub fn statistics(population: &[(f64, Bezier)]) -> Statistics {
+    let n = population.len() as f64;
+    let center = |control: fn(&Bezier) -> Point| {
+        let (x, y) = population.iter().fold((0.0, 0.0), |(x, y), (_, line)| {
+            let p = control(line);
+            (x + p.x, y + p.y)
+        });
+        Point { x: x / n, y: y / n }
+    };
+    let center1 = center(|line| line.control1);
+    let center2 = center(|line| line.control2);
+    let diversity = population
+        .iter()
+        .map(|(_, line)| {
+            (line.control1.distance(&center1) + line.control2.distance(&center2)) / 2.0
+        })
+        .sum::<f64>()
+        / n;
+
+    let middle = population.len() / 2;
+    let median = if population.len().is_multiple_of(2) {
+        (population[middle - 1].0 + population[middle].0) / 2.0
+    } else {
+        population[middle].0
+    };
+
+    Statistics {
+        best: population[0].0,
+        mean: population.iter().map(|(score, _)| score).sum::<f64>() / n,
+        median,
+        worst: population[population.len() - 1].0,
+        diversity,
+    }
+}
+
+const BOUNDARY_RADIUS: i64 = 2;
+const EDGE_MAGNITUDE: f64 = 100.0;
+
+type Gradient = ImageBuffer<Luma<i16>, Vec<i16>>;
+
+/* La imagen que ven las funciones de fitness. El gradiente de Sobel se calcula una sola vez */
+pub struct Picture {
+    pixels: GrayImage,
+    gradients: Option<(Gradient, Gradient)>,
+}
+
+impl Picture {
+    pub fn new(pixels: GrayImage, config: &GeneticConfig) -> Picture {
+        let gradients = matches!(config.fitness, Fitness::Gradient)
+            .then(|| (horizontal_sobel(&pixels), vertical_sobel(&pixels)));
+        Picture { pixels, gradients }
+    }
+}
+
+impl Deref for Picture {
+    type Target = GrayImage;
+
+    fn deref(&self) -> &GrayImage {
+        &self.pixels
+    }
+}
+
+pub fn evaluate(image: &Picture, line: &Bezier, config: &GeneticConfig) -> f64 {
+    let pixel = |point: &Point| {
+        let (x, y) = (point.x as u32, point.y as u32);
+        image.in_bounds(x, y).then_some((x, y))
+    };
+    match config.fitness {
+        Fitness::Stroke | Fitness::Boundary => line
+            .iter()
+            .map(|point| {
+                let hit = pixel(&point).is_some_and(|(x, y)| match config.fitness {
+                    Fitness::Boundary => on_boundary(image, x, y, config),
+                    _ => is_dark(image, x, y, config),
+                });
+                if hit {
+                    1.0
+                } else {
+                    -100.0
+                }
+            })
+            .sum(),
+        Fitness::Gradient => {
+            /* Tangente aproximada con los puntos vecinos */
+            let points: Vec<Point> = line.iter().collect();
+            (0..points.len())
+                .map(|i| {
+                    let previous = &points[i.saturating_sub(1)];
+                    let next = &points[(i + 1).min(points.len() - 1)];
+                    pixel(&points[i])
+                        .and_then(|(x, y)| {
+                            edge_alignment(image, x, y, next.x - previous.x, next.y - previous.y)
+                        })
+                        .unwrap_or(-100.0)
+                })
+                .sum()
+        }
+    }
+}
+
+pub fn is_dark(image: &GrayImage, x: u32, y: u32, config: &GeneticConfig) -> bool {
+    (image.get_pixel(x, y)[0] < config.threshold) != config.invert
+}
+
+/* Umbral de Otsu, los píxeles por encima del nivel son claros */
+pub fn otsu_threshold(image: &GrayImage) -> u8 {
+    otsu_level(image).saturating_add(1)
+}
+
+/* Píxeles oscuros en negro y claros en blanco, tal y como los ve evaluate */
+pub fn binarize(image: &GrayImage, config: &GeneticConfig) -> GrayImage {
+    GrayImage::from_fn(image.width(), image.height(), |x, y| {
+        if is_dark(image, x, y, config) {
+            Luma([0])
         } else {
-            eval -= 100.0;
+            Luma([255])
+        }
+    })
+}
+
+/* Hay píxeles oscuros y claros alrededor. Fuera de la imagen se considera fondo claro */
+pub fn on_boundary(image: &GrayImage, x: u32, y: u32, config: &GeneticConfig) -> bool {
+    let mut dark = false;
+    let mut light = false;
+    for dy in -BOUNDARY_RADIUS..=BOUNDARY_RADIUS {
+        for dx in -BOUNDARY_RADIUS..=BOUNDARY_RADIUS {
+            let nx = x as i64 + dx;
+            let ny = y as i64 + dy;
+            if nx >= 0 && ny >= 0 && image.in_bounds(nx as u32, ny as u32) {
+                if is_dark(image, nx as u32, ny as u32, config) {
+                    dark = true;
+                } else {
+                    light = true;
+                }
+            } else {
+                light = true;
+            }
+        }
+    }
+    dark && light
+}
+
+/* Entre 0 y 1 según lo paralela que sea la curva al borde, None si no hay borde */
+fn edge_alignment(image: &Picture, x: u32, y: u32, tx: f64, ty: f64) -> Option<f64> {
+    let (horizontal, vertical) = image.gradients.as_ref().unwrap();
+    let gx = horizontal.get_pixel(x, y)[0] as f64;
+    let gy = vertical.get_pixel(x, y)[0] as f64;
+    let magnitude = (gx * gx + gy * gy).sqrt();
+    let length = (tx * tx + ty * ty).sqrt();
+    if magnitude < EDGE_MAGNITUDE || length == 0.0 {
+        return None;
+    }
+    /* El gradiente es perpendicular al borde: el seno del ángulo entre ambos */
+    let sin = (gx * ty - gy * tx).abs() / (magnitude * length);
+    Some(sin.min(1.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn p(x: f64, y: f64) -> Point {
+        Point { x, y }
+    }
+
+    #[test]
+    fn diagonal_edge() {
+        let config = GeneticConfig {
+            fitness: Fitness::Gradient,
+            population: 100,
+            good_ones: 50,
+            max_generations: 20,
+            ..GeneticConfig::default()
+        };
+        let pixels =
+            GrayImage::from_fn(100, 100, |x, y| if y > x { Luma([0]) } else { Luma([255]) });
+        let image = Picture::new(pixels, &config);
+        let line = Bezier {
+            start: p(10.0, 50.0),
+            control1: p(24.0, 36.0),
+            control2: p(38.0, 22.0),
+            end: p(52.0, 8.0),
+        };
+        assert!(evaluate(&image, &line, &config).is_finite());
+        let segment = Segment {
+            contour: 0,
+            index: 0,
+            start: line.start,
+            end: line.end,
+        };
+        let result = algorithm(
+            &image,
+            segment,
+            None,
+            &config,
+            &AtomicBool::new(false),
+            &|_| {},
+        )
+        .unwrap();
+        assert!(result.score.is_finite());
+    }
+
+    fn line() -> Bezier {
+        Bezier {
+            start: p(0.0, 0.0),
+            control1: p(10.0, 20.0),
+            control2: p(30.0, 25.0),
+            end: p(40.0, 0.0),
+        }
+    }
+
+    #[test]
+    fn crossover_equal_parents() {
+        let parent = line();
+        for kind in [
+            Crossover::Blend(0.5),
+            Crossover::SimulatedBinary(2.0),
+            Crossover::Arithmetic,
+        ] {
+            let config = GeneticConfig {
+                crossover: kind,
+                ..GeneticConfig::default()
+            };
+            let mut rng = StdRng::seed_from_u64(0);
+            for _ in 0..100 {
+                let child = crossover(&parent, &parent, &config, &mut rng);
+                assert!(child.control1.distance(&parent.control1) < 1e-9);
+                assert!(child.control2.distance(&parent.control2) < 1e-9);
+                assert!(child.start == parent.start && child.end == parent.end);
+            }
+        }
+    }
+
+    #[test]
+    fn select_survivors() {
+        let population: Vec<(f64, Bezier)> =
+            (0..50).map(|i| (40.0 - i as f64 * 3.0, line())).collect();
+        for selection in [
+            Selection::Truncation,
+            Selection::Tournament(3),
+            Selection::Roulette,
+            Selection::Rank,
+        ] {
+            let config = GeneticConfig {
+                selection,
+                good_ones: 20,
+                elitism: 4,
+                ..GeneticConfig::default()
+            };
+            let mut rng = StdRng::seed_from_u64(0);
+            let survivors = select(&population, &config, &mut rng);
+            assert_eq!(survivors.len(), 20);
+            assert_eq!(survivors[..4], [0, 1, 2, 3]);
+            assert!(survivors.iter().all(|&i| i < population.len()));
         }
     }
-    eval
 }
@@ -0,0 +1,429 @@
+/*
+ *  This file is part of Mendel Vectorizer.
+ *
+ *  Mendel Vectorizer is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  Mendel Vectorizer is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with Mendel Vectorizer.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use crate::bezier::{Bezier, Point};
+use imageproc::corners::Corner;
+use std::fs;
+use std::path::PathBuf;
+
+/* Los comandos relativos acumulan errores de redondeo, al cerrar se da por llegado al inicio */
+const CLOSE_TOLERANCE: f64 = 1e-6;
+
+enum Token {
+    Command(char),
+    Number(f64),
+}
+
+/* Lee los paths de un SVG (comandos M, L, H, V, C, S y Z), un contorno por subpath */
+pub fn import(filename: PathBuf) -> Vec<Vec<Bezier>> {
+    let svg = fs::read_to_string(filename).unwrap();
+    let mut contours = Vec::new();
+    let mut rest = svg.as_str();
+    while let Some(start) = rest.find("<path") {
+        rest = &rest[start..];
+        let tag = &rest[..rest.find('>').unwrap_or(rest.len())];
+        if let Some(d) = attribute(tag, "d") {
+            contours.append(&mut parse_path(d));
+        }
+        rest = &rest[tag.len()..];
+    }
+
+    /* Las esquinas son píxeles, los extremos de cada curva también */
+    for contour in contours.iter_mut() {
+        for line in contour.iter_mut() {
+            line.start = snap(&line.start);
+            line.end = snap(&line.end);
+        }
+    }
+    contours
+}
+
+pub fn corners(contour: &[Bezier]) -> Vec<Corner> {
+    let corner = |p: &Point| Corner {
+        x: p.x as u32,
+        y: p.y as u32,
+        score: f32::INFINITY,
+    };
+    let mut corners = vec![corner(&contour[0].start)];
+    corners.extend(contour.iter().map(|line| corner(&line.end)));
+    corners
+}
+
+fn snap(point: &Point) -> Point {
+    Point {
+        x: point.x.round().max(0.0),
+        y: point.y.round().max(0.0),
+    }
+}
+
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    for quote in ['"', '\''] {
+        let pattern = format!(" {}={}", name, quote);
+        if let Some(start) = tag.find(&pattern) {
+            let value = &tag[start + pattern.len()..];
+            return value.find(quote).map(|end| &value[..end]);
+        }
+    }
+    None
+}
+
+fn parse_path(d: &str) -> Vec<Vec<Bezier>> {
+    let tokens = tokenize(d);
+    let mut contours = Vec::new();
+    let mut contour: Vec<Bezier> = Vec::new();
+    let mut current = Point { x: 0.0, y: 0.0 };
+    let mut subpath_start = current;
+    let mut previous_control: Option<Point> = None;
+    let mut command = ' ';
+    let mut i = 0;
+
+    while i < tokens.len() {
+        match tokens[i] {
+            Token::Command(c) => {
+                command = c;
+                i += 1;
+            }
+            /* Repetición implícita del comando anterior, tras un M es un L */
+            Token::Number(_) => {
+                command = match command {
+                    'M' => 'L',
+                    'm' => 'l',
+                    c => c,
+                };
+            }
+        }
+
+        if command == 'Z' || command == 'z' {
+            if current.distance(&subpath_start) > CLOSE_TOLERANCE {
+                contour.push(straight(current, subpath_start));
+            } else if let Some(last) = contour.last_mut() {
+                last.end = subpath_start;
+            }
+            current = subpath_start;
+            previous_control = None;
+            if !contour.is_empty() {
+                contours.push(std::mem::take(&mut contour));
+            }
+            while let Some(Token::Number(_)) = tokens.get(i) {
+                i += 1;
+            }
+            continue;
+        }
+
+        let arity = match command.to_ascii_uppercase() {
+            'M' | 'L' => 2,
+            'H' | 'V' => 1,
+            'C' => 6,
+            'S' => 4,
+            _ => {
+                /* Comando no soportado, se ignoran sus argumentos */
+                while let Some(Token::Number(_)) = tokens.get(i) {
+                    i += 1;
+                }
+                continue;
+            }
+        };
+        let mut values = Vec::new();
+        while values.len() < arity {
+            match tokens.get(i) {
+                Some(Token::Number(n)) => values.push(*n),
+                _ => break,
+            }
+            i += 1;
+        }
+        if values.len() < arity {
+            continue;
+        }
+
+        let relative = command.is_ascii_lowercase();
+        let point = |x: f64, y: f64| {
+            if relative {
+                Point {
+                    x: current.x + x,
+                    y: current.y + y,
+                }
+            } else {
+                Point { x, y }
+            }
+        };
+        let mut control = None;
+        match command.to_ascii_uppercase() {
+            'M' => {
+                if !contour.is_empty() {
+                    contours.push(std::mem::take(&mut contour));
+                }
+                current = point(values[0], values[1]);
+                subpath_start = current;
+            }
+            'L' => {
+                let end = point(values[0], values[1]);
+                contour.push(straight(current, end));
+                current = end;
+            }
+            'H' => {
+                let x = if relative {
+                    current.x + values[0]
+                } else {
+                    values[0]
+                };
+                let end = Point { x, y: current.y };
+                contour.push(straight(current, end));
+                current = end;
+            }
+            'V' => {
+                let y = if relative {
+                    current.y + values[0]
+                } else {
+                    values[0]
+                };
+                let end = Point { x: current.x, y };
+                contour.push(straight(current, end));
+                current = end;
+            }
+            'C' | 'S' => {
+                let (control1, control2, end) = if command.eq_ignore_ascii_case(&'C') {
+                    (
+                        point(values[0], values[1]),
+                        point(values[2], values[3]),
+                        point(values[4], values[5]),
+                    )
+                } else {
+                    /* Reflejo del control anterior, o el punto actual si no lo hay */
+                    let control1 = previous_control.map_or(current, |previous| Point {
+                        x: 2.0 * current.x - previous.x,
+                        y: 2.0 * current.y - previous.y,
+                    });
+                    (
+                        control1,
+                        point(values[0], values[1]),
+                        point(values[2], values[3]),
+                    )
+                };
+                contour.push(Bezier {
+                    start: current,
+                    control1,
+                    control2,
+                    end,
+                });
+                control = Some(control2);
+                current = end;
+            }
+            _ => (),
+        }
+        previous_control = control;
+    }
+    if !contour.is_empty() {
+        contours.push(contour);
+    }
+    contours
+}
+
+fn straight(start: Point, end: Point) -> Bezier {
+    Bezier {
+        start,
+        control1: Point {
+            x: start.x + (end.x - start.x) / 3.0,
+            y: start.y + (end.y - start.y) / 3.0,
+        },
+        control2: Point {
+            x: start.x + 2.0 * (end.x - start.x) / 3.0,
+            y: start.y + 2.0 * (end.y - start.y) / 3.0,
+        },
+        end,
+    }
+}
+
+fn tokenize(d: &str) -> Vec<Token> {
+    let chars: Vec<char> = d.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_ascii_alphabetic() && c != 'e' && c != 'E' {
+            tokens.push(Token::Command(c));
+            i += 1;
+        } else if c.is_ascii_digit() || c == '-' || c == '+' || c == '.' {
+            let start = i;
+            let mut dot = false;
+            i += 1;
+            if c == '.' {
+                dot = true;
+            }
+            while i < chars.len() {
+                let c = chars[i];
+                if c.is_ascii_digit() {
+                    i += 1;
+                } else if c == '.' && !dot {
+                    dot = true;
+                    i += 1;
+                } else if (c == 'e' || c == 'E') && i + 1 < chars.len() {
+                    i += 1;
+                    if chars[i] == '-' || chars[i] == '+' {
+                        i += 1;
+                    }
+                    dot = true;
+                } else {
+                    break;
+                }
+            }
+            let number: String = chars[start..i].iter().collect();
+            if let Ok(n) = number.parse() {
+                tokens.push(Token::Number(n));
+            }
+        } else {
+            i += 1;
+        }
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numbers(d: &str) -> Vec<f64> {
+        tokenize(d)
+            .into_iter()
+            .filter_map(|token| match token {
+                Token::Number(n) => Some(n),
+                Token::Command(_) => None,
+            })
+            .collect()
+    }
+
+    fn commands(d: &str) -> String {
+        tokenize(d)
+            .into_iter()
+            .filter_map(|token| match token {
+                Token::Command(c) => Some(c),
+                Token::Number(_) => None,
+            })
+            .collect()
+    }
+
+    fn p(x: f64, y: f64) -> Point {
+        Point { x, y }
+    }
+
+    #[test]
+    fn tokenize_separators() {
+        assert_eq!(numbers("M10,20L30-40"), vec![10.0, 20.0, 30.0, -40.0]);
+        assert_eq!(commands("M10,20L30-40z"), "MLz");
+        assert_eq!(numbers(" 1 ,\n\t2 "), vec![1.0, 2.0]);
+        assert_eq!(numbers("+3-4"), vec![3.0, -4.0]);
+    }
+
+    #[test]
+    fn tokenize_dots() {
+        assert_eq!(numbers("1.5.5"), vec![1.5, 0.5]);
+        assert_eq!(numbers("-.5-.5"), vec![-0.5, -0.5]);
+        assert_eq!(numbers(".5.5.5"), vec![0.5, 0.5, 0.5]);
+        assert_eq!(numbers("0.5"), vec![0.5]);
+    }
+
+    #[test]
+    fn tokenize_exponents() {
+        assert_eq!(numbers("1e-2"), vec![0.01]);
+        assert_eq!(numbers("1E+2 2e1"), vec![100.0, 20.0]);
+        assert_eq!(numbers("2e1.5"), vec![20.0, 0.5]);
+        assert_eq!(numbers("-1.5e1-3"), vec![-15.0, -3.0]);
+        assert_eq!(commands("1e1E2"), "");
+    }
+
+    #[test]
+    fn implicit_lineto() {
+        let contours = parse_path("M0 0 10 0 10 10");
+        assert_eq!(contours.len(), 1);
+        let ends: Vec<Point> = contours[0].iter().map(|line| line.end).collect();
+        assert!(ends == vec![p(10.0, 0.0), p(10.0, 10.0)]);
+
+        let contours = parse_path("m5 5 10 0 0 10");
+        let ends: Vec<Point> = contours[0].iter().map(|line| line.end).collect();
+        assert!(ends == vec![p(15.0, 5.0), p(15.0, 15.0)]);
+    }
+
+    #[test]
+    fn horizontal_vertical_close() {
+        let contours = parse_path("M0 0H10V10h-10z");
+        assert_eq!(contours.len(), 1);
+        let contour = &contours[0];
+        assert_eq!(contour.len(), 4);
+        assert!(contour[3].start == p(0.0, 10.0) && contour[3].end == p(0.0, 0.0));
+    }
+
+    #[test]
+    fn smooth_reflection() {
+        let contours = parse_path("M0 0C0 10 10 10 10 0S20 -10 20 0");
+        let contour = &contours[0];
+        assert_eq!(contour.len(), 2);
+        assert!(contour[1].control1 == p(10.0, -10.0));
+        assert!(contour[1].control2 == p(20.0, -10.0));
+
+        let contours = parse_path("m0 0c0 10 10 10 10 0s10-10 10 0");
+        assert!(contours[0][1].control1 == p(10.0, -10.0));
+        assert!(contours[0][1].control2 == p(20.0, -10.0));
+        assert!(contours[0][1].end == p(20.0, 0.0));
+
+        /* Sin curva anterior el primer control es el punto actual */
+        let contours = parse_path("M0 0L5 0S10 5 10 0");
+        assert!(contours[0][1].control1 == p(5.0, 0.0));
+    }
+
+    #[test]
+    fn subpaths() {
+        let contours = parse_path("M0 0L10 0M20 20L30 20zm5 5l1 1");
+        assert_eq!(contours.len(), 3);
+        assert!(contours[2][0].start == p(25.0, 25.0));
+    }
+
+    #[test]
+    fn fill_round_trip() {
+        use crate::export::{self, FillRule, SvgOptions};
+        use crate::segment;
+
+        let square = [p(10.0, 10.0), p(30.0, 10.0), p(30.0, 30.0), p(10.0, 30.0)];
+        let lines: Vec<Bezier> = (0..4)
+            .map(|i| straight(square[i], square[(i + 1) % 4]))
+            .collect();
+        let filename = std::env::temp_dir().join(format!("mendel-fill-{}.svg", std::process::id()));
+        let options = SvgOptions {
+            precision: 2,
+            relative: true,
+            fill: Some(FillRule::EvenOdd),
+            source: None,
+        };
+        export::export(std::slice::from_ref(&lines), filename.clone(), &options);
+        let contours = import(filename.clone());
+        std::fs::remove_file(filename).unwrap();
+
+        assert_eq!(contours.len(), 1);
+        let corners = vec![corners(&contours[0])];
+        let segments = segment::segments(&corners, true);
+        assert_eq!(segments.len(), 4);
+        for (segment, line) in segments.iter().zip(&lines) {
+            assert!(segment.start == line.start && segment.end == line.end);
+        }
+    }
+
+    #[test]
+    fn relative_close_drift() {
+        /* .1 + .2 - .3 no es exactamente 0 */
+        let contours = parse_path("M0 0l.1 1 .2-1-.3 0z");
+        assert_eq!(contours[0].len(), 3);
+        assert!(contours[0][2].end == p(0.0, 0.0));
+    }
+}
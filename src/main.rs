@@ -24,17 +24,38 @@ use gtk::{Button, DrawingArea, Window};
 
 use clap::{App, Arg};
 
-use std::cell::RefCell;
+use image::imageops::colorops::grayscale;
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Cursor, Write};
+use std::path::PathBuf;
 use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::channel;
-use std::thread;
+use std::sync::Arc;
 
 mod bezier;
+mod cmaes;
 mod corner;
 mod export;
+mod fit;
 mod genetic;
+mod import;
+mod optimizer;
+mod preprocess;
+mod segment;
+
+use optimizer::Optimizer;
+use segment::Results;
 
 const CORNER_RADIUS: f64 = 5.0;
+/* Generaciones entre cada muestra de la población que se dibuja */
+const SNAPSHOT_EVERY: usize = 10;
+
+/* Mejor curva y muestra de la población de los segmentos en curso */
+type Evolving = HashMap<(usize, usize), (bezier::Bezier, Vec<bezier::Bezier>)>;
 
 fn gtk_open_file() -> Option<std::path::PathBuf> {
     let open_dialog = gtk::FileChooserDialog::new(
@@ -58,11 +79,39 @@ fn gtk_open_file() -> Option<std::path::PathBuf> {
     None
 }
 
-fn main() {
-    if gtk::init().is_err() {
-        panic!("Failed to initialize GTK");
+fn import_svg(
+    filename: PathBuf,
+    image: &genetic::Picture,
+    config: &genetic::GeneticConfig,
+) -> (Vec<Vec<Corner>>, Results) {
+    let mut corners = Vec::new();
+    let mut lines = Results::new();
+    for (i, contour) in import::import(filename).into_iter().enumerate() {
+        corners.push(import::corners(&contour));
+        for (index, curve) in contour.into_iter().enumerate() {
+            lines.insert(genetic::SegmentResult::new(
+                image, i, index, curve, 0, config,
+            ));
+        }
     }
+    (corners, lines)
+}
+
+fn pixbuf(image: &image::GrayImage) -> gdk_pixbuf::Pixbuf {
+    let (width, height) = image.dimensions();
+    let rgb = image::DynamicImage::ImageLuma8(image.clone()).to_rgb8();
+    gdk_pixbuf::Pixbuf::from_mut_slice(
+        rgb.into_raw(),
+        gdk_pixbuf::Colorspace::Rgb,
+        false,
+        8,
+        width as i32,
+        height as i32,
+        width as i32 * 3,
+    )
+}
 
+fn main() {
     let matches = App::new("Mender Vectorizer")
         .version("1.0")
         .author("Adrián Arroyo Calle <adrian.arroyocalle@gmail.com>")
@@ -73,15 +122,537 @@ fn main() {
                 .required(false)
                 .index(1),
         )
+        .arg(
+            Arg::with_name("output")
+                .long("output")
+                .help("Vectorize without GUI and save the result to this file. Ctrl-C stops and saves the finished segments")
+                .takes_value(true)
+                .requires("INPUT"),
+        )
+        .arg(
+            Arg::with_name("import")
+                .long("import")
+                .help("Start from the corners and curves of a previous SVG. Without GUI, FAST 9 corners are used otherwise")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("log")
+                .long("log")
+                .help("Write the progress of the genetic algorithm to this file")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("stats")
+                .long("stats")
+                .help("Write statistics of every generation to this file, as CSV or as JSON lines if it ends in .jsonl")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("seed")
+                .long("seed")
+                .help("Seed of the genetic algorithm, random if not given")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("threads")
+                .long("threads")
+                .help("Number of segments fitted at the same time, one per CPU by default")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("parallel-evaluation")
+                .long("parallel-evaluation")
+                .help("Also evaluate the population of every generation in parallel, useful with few long segments"),
+        )
+        .arg(
+            Arg::with_name("optimizer")
+                .long("optimizer")
+                .help("Fit the curves with the genetic algorithm, with CMA-ES or with least squares on the traced stroke")
+                .takes_value(true)
+                .possible_values(["genetic", "cmaes", "least-squares"])
+                .default_value("genetic"),
+        )
+        .arg(
+            Arg::with_name("cmaes-population")
+                .long("cmaes-population")
+                .help("Curves sampled in every generation of CMA-ES")
+                .takes_value(true)
+                .default_value("20"),
+        )
+        .arg(
+            Arg::with_name("init")
+                .long("init")
+                .help("Curves added to the random initial population: a straight line, control points on nearby dark pixels, the result of the previous run and a least squares fit")
+                .takes_value(true)
+                .use_delimiter(true)
+                .possible_values(["straight", "dark", "previous", "fit", "none"])
+                .default_value("straight,previous"),
+        )
+        .arg(
+            Arg::with_name("selection")
+                .long("selection")
+                .help("How the survivors of every generation are chosen")
+                .takes_value(true)
+                .possible_values(["truncation", "tournament", "roulette", "rank"])
+                .default_value("truncation"),
+        )
+        .arg(
+            Arg::with_name("tournament-size")
+                .long("tournament-size")
+                .help("Individuals competing in every tournament")
+                .takes_value(true)
+                .default_value("3"),
+        )
+        .arg(
+            Arg::with_name("elitism")
+                .long("elitism")
+                .help("Best individuals that always survive")
+                .takes_value(true)
+                .default_value("1"),
+        )
+        .arg(
+            Arg::with_name("crossover")
+                .long("crossover")
+                .help("How the control points of two parents are combined: BLX-α, simulated binary (SBX) or weighted mean")
+                .takes_value(true)
+                .possible_values(["blend", "sbx", "arithmetic"])
+                .default_value("blend"),
+        )
+        .arg(
+            Arg::with_name("blend-alpha")
+                .long("blend-alpha")
+                .help("How far beyond the parents BLX-α children can be, relative to the distance between parents")
+                .takes_value(true)
+                .default_value("0.5"),
+        )
+        .arg(
+            Arg::with_name("sbx-eta")
+                .long("sbx-eta")
+                .help("Distribution index of SBX, higher values give children closer to the parents")
+                .takes_value(true)
+                .default_value("2"),
+        )
+        .arg(
+            Arg::with_name("crossover-rate")
+                .long("crossover-rate")
+                .help("Probability of crossing the parents instead of copying the first one")
+                .takes_value(true)
+                .default_value("0.9"),
+        )
+        .arg(
+            Arg::with_name("mutation-rate")
+                .long("mutation-rate")
+                .help("Probability of mutating every individual")
+                .takes_value(true)
+                .default_value("0.1"),
+        )
+        .arg(
+            Arg::with_name("mutation")
+                .long("mutation")
+                .help("Keep the mutation strength fixed, shrink it with the generations or adapt it with the 1/5 success rule")
+                .takes_value(true)
+                .possible_values(["fixed", "schedule", "one-fifth"])
+                .default_value("fixed"),
+        )
+        .arg(
+            Arg::with_name("mutation-scale")
+                .long("mutation-scale")
+                .help("Initial standard deviation of mutations, relative to the distance between corners")
+                .takes_value(true)
+                .default_value("0.5"),
+        )
+        .arg(
+            Arg::with_name("fitness")
+                .long("fitness")
+                .help("Follow dark strokes, the boundary of dark regions or image edges (Sobel gradient)")
+                .takes_value(true)
+                .possible_values(["stroke", "boundary", "gradient"]),
+        )
+        .arg(
+            Arg::with_name("scale")
+                .long("scale")
+                .help("Resize the image by this factor before vectorizing")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("gaussian-blur")
+                .long("gaussian-blur")
+                .help("Apply a gaussian blur with this sigma")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("median-blur")
+                .long("median-blur")
+                .help("Apply a median filter with this radius")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("normalize")
+                .long("normalize")
+                .help("Stretch the contrast of the image"),
+        )
+        .arg(
+            Arg::with_name("open")
+                .long("open")
+                .help("Morphological opening of the drawing with this radius, removes small spots")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("close")
+                .long("close")
+                .help("Morphological closing of the drawing with this radius, fills small gaps")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("despeckle")
+                .long("despeckle")
+                .help("Remove dark spots smaller than this area in pixels")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("threshold")
+                .long("threshold")
+                .help("Gray level below which a pixel is dark, or otsu to compute it from the image")
+                .takes_value(true)
+                .default_value("200"),
+        )
+        .arg(
+            Arg::with_name("invert")
+                .long("invert")
+                .help("Light drawing on a dark background"),
+        )
+        .arg(
+            Arg::with_name("fill")
+                .long("fill")
+                .help("Close the contours and fit them to the boundary of dark regions, exporting filled SVG")
+                .takes_value(true)
+                .possible_values(["evenodd", "nonzero"]),
+        )
+        .arg(
+            Arg::with_name("svg-precision")
+                .long("svg-precision")
+                .help("Decimal places of the coordinates when exporting SVG")
+                .takes_value(true)
+                .default_value("2"),
+        )
+        .arg(
+            Arg::with_name("svg-absolute")
+                .long("svg-absolute")
+                .help("Use absolute path commands when exporting SVG"),
+        )
+        .arg(
+            Arg::with_name("svg-embed")
+                .long("svg-embed")
+                .help("Include the input image and the algorithm parameters when exporting SVG"),
+        )
+        .arg(
+            Arg::with_name("hpgl-tolerance")
+                .long("hpgl-tolerance")
+                .help("Maximum chord error in millimetres when exporting HPGL")
+                .takes_value(true)
+                .default_value("0.1"),
+        )
+        .arg(
+            Arg::with_name("hpgl-units")
+                .long("hpgl-units")
+                .help("Plotter units per millimetre when exporting HPGL")
+                .takes_value(true)
+                .default_value("40"),
+        )
+        .arg(
+            Arg::with_name("hpgl-pen")
+                .long("hpgl-pen")
+                .help("Select a new pen for every contour, or one pen per stroke color. All strokes are black for now, so color draws everything with pen 1")
+                .takes_value(true)
+                .possible_values(["contour", "color"])
+                .default_value("contour"),
+        )
+        .arg(
+            Arg::with_name("tikz-scale")
+                .long("tikz-scale")
+                .help("Centimetres per pixel when exporting TikZ")
+                .takes_value(true)
+                .default_value("0.02"),
+        )
+        .arg(
+            Arg::with_name("tikz-no-flip")
+                .long("tikz-no-flip")
+                .help("Keep the image Y axis (pointing down) when exporting TikZ"),
+        )
         .get_matches();
+
+    let headless = matches.is_present("output");
+    if !headless && gtk::init().is_err() {
+        panic!("Failed to initialize GTK");
+    }
     let inputfile = match matches.value_of("INPUT") {
         Some(file) => file.to_string(),
         None => gtk_open_file().unwrap().to_str().unwrap().to_string(),
     };
     println!("Using input file: {}", inputfile);
 
-    let corners: Rc<RefCell<Vec<Corner>>> = Rc::new(RefCell::new(Vec::new()));
-    let lines: Rc<RefCell<Vec<bezier::Bezier>>> = Rc::new(RefCell::new(Vec::new()));
+    let preprocess_options = preprocess::PreprocessOptions {
+        scale: matches
+            .is_present("scale")
+            .then(|| matches.value_of_t_or_exit("scale")),
+        gaussian_blur: matches
+            .is_present("gaussian-blur")
+            .then(|| matches.value_of_t_or_exit("gaussian-blur")),
+        median_blur: matches
+            .is_present("median-blur")
+            .then(|| matches.value_of_t_or_exit("median-blur")),
+        normalize: matches.is_present("normalize"),
+        open: matches
+            .is_present("open")
+            .then(|| matches.value_of_t_or_exit("open")),
+        close: matches
+            .is_present("close")
+            .then(|| matches.value_of_t_or_exit("close")),
+        invert: matches.is_present("invert"),
+    };
+    /* Se decodifica una sola vez, con --svg-embed se guarda también en PNG para exportarla */
+    let (image, png) = {
+        let original = image::open(&inputfile).unwrap();
+        let png = matches.is_present("svg-embed").then(|| {
+            let mut png = Cursor::new(Vec::new());
+            original
+                .write_to(&mut png, image::ImageOutputFormat::Png)
+                .unwrap();
+            png.into_inner()
+        });
+        (grayscale(&original), png)
+    };
+    let image = preprocess::preprocess(image, &preprocess_options);
+
+    let fill = match matches.value_of("fill") {
+        Some("evenodd") => Some(export::FillRule::EvenOdd),
+        Some("nonzero") => Some(export::FillRule::NonZero),
+        _ => None,
+    };
+    let config = genetic::GeneticConfig {
+        seed: if matches.is_present("seed") {
+            matches.value_of_t_or_exit("seed")
+        } else {
+            rand::random()
+        },
+        fitness: match matches.value_of("fitness") {
+            Some("stroke") => genetic::Fitness::Stroke,
+            Some("boundary") => genetic::Fitness::Boundary,
+            Some("gradient") => genetic::Fitness::Gradient,
+            _ if fill.is_some() => genetic::Fitness::Boundary,
+            _ => genetic::Fitness::Stroke,
+        },
+        threshold: match matches.value_of("threshold") {
+            Some("otsu") => genetic::otsu_threshold(&image),
+            _ => matches.value_of_t_or_exit("threshold"),
+        },
+        invert: matches.is_present("invert"),
+        parallel_evaluation: matches.is_present("parallel-evaluation"),
+        seeding: {
+            let init: Vec<&str> = matches.values_of("init").unwrap().collect();
+            genetic::Seeding {
+                straight: init.contains(&"straight"),
+                dark: init.contains(&"dark"),
+                previous: init.contains(&"previous"),
+                fit: init.contains(&"fit"),
+            }
+        },
+        selection: match matches.value_of("selection") {
+            Some("tournament") => {
+                genetic::Selection::Tournament(matches.value_of_t_or_exit("tournament-size"))
+            }
+            Some("roulette") => genetic::Selection::Roulette,
+            Some("rank") => genetic::Selection::Rank,
+            _ => genetic::Selection::Truncation,
+        },
+        elitism: matches.value_of_t_or_exit("elitism"),
+        crossover: match matches.value_of("crossover") {
+            Some("sbx") => {
+                genetic::Crossover::SimulatedBinary(matches.value_of_t_or_exit("sbx-eta"))
+            }
+            Some("arithmetic") => genetic::Crossover::Arithmetic,
+            _ => genetic::Crossover::Blend(matches.value_of_t_or_exit("blend-alpha")),
+        },
+        crossover_rate: matches.value_of_t_or_exit("crossover-rate"),
+        mutation_rate: matches.value_of_t_or_exit("mutation-rate"),
+        mutation: match matches.value_of("mutation") {
+            Some("schedule") => genetic::Mutation::Schedule,
+            Some("one-fifth") => genetic::Mutation::OneFifth,
+            _ => genetic::Mutation::Fixed,
+        },
+        mutation_scale: matches.value_of_t_or_exit("mutation-scale"),
+        cmaes_population: matches.value_of_t_or_exit("cmaes-population"),
+        ..Default::default()
+    };
+    println!("Using seed: {}", config.seed);
+    println!("Using threshold: {}", config.threshold);
+    let image = if matches.is_present("despeckle") {
+        preprocess::despeckle(&image, matches.value_of_t_or_exit("despeckle"), &config)
+    } else {
+        image
+    };
+    let preprocessed = preprocess_options.is_active() || matches.is_present("despeckle");
+    let image = Arc::new(genetic::Picture::new(image, &config));
+
+    /* Cada segmento es una tarea, los hilos libres roban trabajo a los ocupados */
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(if matches.is_present("threads") {
+            matches.value_of_t_or_exit("threads")
+        } else {
+            num_cpus::get()
+        })
+        .build()
+        .unwrap();
+    let optimizer: Arc<dyn Optimizer> = match matches.value_of("optimizer") {
+        Some("cmaes") => Arc::new(optimizer::CmaEs),
+        Some("least-squares") => Arc::new(optimizer::LeastSquares),
+        _ => Arc::new(optimizer::Genetic),
+    };
+
+    let svg_options = export::SvgOptions {
+        precision: matches.value_of_t_or_exit("svg-precision"),
+        relative: !matches.is_present("svg-absolute"),
+        fill,
+        source: png.map(|png| export::SvgSource {
+            input: inputfile.clone(),
+            png,
+            width: image.width(),
+            height: image.height(),
+            config,
+            optimizer: matches.value_of("optimizer").unwrap().to_string(),
+        }),
+    };
+    let hpgl_options = export::HpglOptions {
+        tolerance: matches.value_of_t_or_exit("hpgl-tolerance"),
+        units_per_mm: matches.value_of_t_or_exit("hpgl-units"),
+        pen: match matches.value_of("hpgl-pen") {
+            Some("color") => export::PenMode::Color,
+            _ => export::PenMode::Contour,
+        },
+    };
+    let tikz_options = export::TikzOptions {
+        scale: matches.value_of_t_or_exit("tikz-scale"),
+        flip_y: !matches.is_present("tikz-no-flip"),
+    };
+    let export_options = Rc::new(export::ExportOptions {
+        svg: svg_options,
+        hpgl: hpgl_options,
+        tikz: tikz_options,
+    });
+
+    let (initial_corners, initial_lines) = match matches.value_of("import") {
+        Some(filename) => import_svg(filename.into(), &image, &config),
+        None => (Vec::new(), Results::new()),
+    };
+
+    let mut log = matches
+        .value_of("log")
+        .map(|filename| File::create(filename).unwrap());
+    let mut stats = matches
+        .value_of("stats")
+        .map(|filename| export::StatisticsLog::create(filename.into()));
+
+    /* Sin interfaz gráfica */
+    if let Some(output) = matches.value_of("output") {
+        let corners = if initial_corners.is_empty() {
+            vec![corner::fast9(&image)]
+        } else {
+            initial_corners
+        };
+        let mut lines = initial_lines;
+        let pending = lines.pending(&corners, fill.is_some());
+        let total = lines.total();
+
+        /* Ctrl-C cancela y se guardan los segmentos terminados, la segunda vez sale sin guardar */
+        let cancel = Arc::new(AtomicBool::new(false));
+        let c = cancel.clone();
+        ctrlc::set_handler(move || {
+            if c.swap(true, Ordering::Relaxed) {
+                std::process::exit(130);
+            }
+            println!("Stopping, press Ctrl-C again to quit without saving");
+        })
+        .unwrap();
+
+        let (tx, rx) = channel();
+        for (segment, previous) in pending {
+            let tx = tx.clone();
+            let image = image.clone();
+            let cancel = cancel.clone();
+            let optimizer = optimizer.clone();
+            pool.spawn(move || {
+                optimizer.optimize(&image, segment, previous, &config, &cancel, &|event| {
+                    tx.send(event).unwrap()
+                });
+            });
+        }
+        drop(tx);
+        let mut running = HashMap::new();
+        for event in rx {
+            if let Some(log) = log.as_mut() {
+                writeln!(log, "{}", event).unwrap();
+            }
+            if let Some(stats) = stats.as_mut() {
+                stats.write(&event);
+            }
+            if matches!(event, genetic::Event::Finished(_)) {
+                /* Borra la línea de estado */
+                println!("\r\x1b[K{}", event);
+            }
+            match event {
+                genetic::Event::Started(segment) => {
+                    running.insert((segment.contour, segment.index), 0.0);
+                }
+                genetic::Event::Generation {
+                    segment,
+                    statistics,
+                    ..
+                } => {
+                    running.insert((segment.contour, segment.index), statistics.best);
+                }
+                genetic::Event::Snapshot { .. } => {}
+                genetic::Event::Finished(line) => {
+                    running.remove(&(line.contour, line.index));
+                    lines.insert(line);
+                }
+            }
+            let best = running.values().cloned().fold(f64::NEG_INFINITY, f64::max);
+            print!(
+                "\rSegments: {}/{}, running: {}",
+                lines.len(),
+                total,
+                running.len()
+            );
+            if !running.is_empty() {
+                print!(", best: {:.2}", best);
+            }
+            std::io::stdout().flush().unwrap();
+        }
+        println!();
+
+        export::export_file(&lines.to_vec(), output.into(), &export_options);
+        println!("Saved {}", output);
+        return;
+    }
+
+    /* Si la imagen se ha modificado se muestra tal y como la ve el algoritmo */
+    let background = if preprocessed {
+        pixbuf(&image)
+    } else {
+        gdk_pixbuf::Pixbuf::from_file(&inputfile).unwrap()
+    };
+    let binarized = pixbuf(&genetic::binarize(&image, &config));
+
+    let corners: Rc<RefCell<Vec<Vec<Corner>>>> = Rc::new(RefCell::new(initial_corners));
+    let lines: Rc<RefCell<Results>> = Rc::new(RefCell::new(initial_lines));
+    let evolving: Rc<RefCell<Evolving>> = Rc::new(RefCell::new(HashMap::new()));
+    let config = genetic::GeneticConfig {
+        snapshot_every: SNAPSHOT_EVERY,
+        ..config
+    };
+    /* Cada pulsación de Go! es una ejecución, los resultados de las canceladas se descartan */
+    let run = Rc::new(Cell::new(0));
+    let cancel = Rc::new(RefCell::new(Arc::new(AtomicBool::new(false))));
 
     let (tx, rx) = channel();
 
@@ -92,8 +663,12 @@ fn main() {
     let drawing: DrawingArea = builder.object("drawingArea").unwrap();
     let clear: Button = builder.object("clear").unwrap();
     let fast9: Button = builder.object("fast9").unwrap();
+    let preview: gtk::CheckButton = builder.object("preview").unwrap();
+    let live: gtk::CheckButton = builder.object("live").unwrap();
+    let import: Button = builder.object("import").unwrap();
     let export: Button = builder.object("export").unwrap();
     let go: Button = builder.object("go").unwrap();
+    let stop: Button = builder.object("stop").unwrap();
     let progress: gtk::ProgressBar = builder.object("progress").unwrap();
 
     window.show_all();
@@ -108,7 +683,19 @@ fn main() {
     let c = corners.clone();
     let l = lines.clone();
     let d = drawing.clone();
+    let r = run.clone();
+    let k = cancel.clone();
+    let g = go.clone();
+    let s = stop.clone();
+    let p = progress.clone();
+    let e = evolving.clone();
     clear.connect_clicked(move |_| {
+        k.borrow().store(true, Ordering::Relaxed);
+        r.set(r.get() + 1);
+        e.borrow_mut().clear();
+        g.set_sensitive(true);
+        s.set_sensitive(false);
+        p.set_fraction(0.0);
         let corners = c.clone();
         (*corners.borrow_mut()).clear();
         let lines = l.clone();
@@ -120,20 +707,62 @@ fn main() {
 
     let c = corners.clone();
     let d = drawing.clone();
-    let i = inputfile.clone();
+    let im = image.clone();
     fast9.connect_clicked(move |_| {
         let corners = c.clone();
-        let inputfile = i.clone();
         {
             let mut corners = corners.borrow_mut();
-            let mut fast9 = corner::fast9(inputfile);
-            corners.append(&mut fast9);
+            let mut fast9 = corner::fast9(&im);
+            if corners.is_empty() {
+                corners.push(Vec::new());
+            }
+            corners.last_mut().unwrap().append(&mut fast9);
+        }
+        d.queue_draw();
+    });
+
+    /* Preview threshold */
+    let d = drawing.clone();
+    preview.connect_toggled(move |_| {
+        d.queue_draw();
+    });
+
+    /* Show evolution */
+    let d = drawing.clone();
+    live.connect_toggled(move |_| {
+        d.queue_draw();
+    });
+
+    /* Import SVG */
+    let c = corners.clone();
+    let l = lines.clone();
+    let d = drawing.clone();
+    let im = image.clone();
+    let r = run.clone();
+    let k = cancel.clone();
+    let g = go.clone();
+    let s = stop.clone();
+    let p = progress.clone();
+    let e = evolving.clone();
+    import.connect_clicked(move |_| {
+        if let Some(filename) = gtk_open_file() {
+            /* Como Clear: se descarta la ejecución en curso */
+            k.borrow().store(true, Ordering::Relaxed);
+            r.set(r.get() + 1);
+            e.borrow_mut().clear();
+            g.set_sensitive(true);
+            s.set_sensitive(false);
+            p.set_fraction(0.0);
+            let (corners, lines) = import_svg(filename, &im, &config);
+            *c.borrow_mut() = corners;
+            *l.borrow_mut() = lines;
         }
         d.queue_draw();
     });
 
-    /* Export as SVG */
+    /* Export, the format depends on the extension */
     let l = lines.clone();
+    let e = export_options.clone();
     export.connect_clicked(move |_| {
         let lines = l.clone();
         let save_dialog = gtk::FileChooserDialog::new(
@@ -148,7 +777,7 @@ fn main() {
 
         if save_dialog.run() == gtk::ResponseType::Ok.into() {
             if let Some(filename) = save_dialog.filename() {
-                export::export(&lines.borrow(), filename);
+                export::export_file(&lines.borrow().to_vec(), filename, &e);
             }
         }
         save_dialog.hide();
@@ -156,63 +785,76 @@ fn main() {
 
     /* Execute algorithm */
     let c = corners.clone();
-    let i = inputfile.clone();
+    let l = lines.clone();
+    let im = image.clone();
     let tx = tx.clone();
+    let r = run.clone();
+    let k = cancel.clone();
+    let s = stop.clone();
+    let e = evolving.clone();
     go.connect_clicked(move |widget| {
-        let corners = c.clone();
-        let corners = corners.borrow();
-        let inputfile = i.clone();
+        let corners = c.borrow();
+        let mut lines = l.borrow_mut();
+        let pending = lines.pending(&corners, fill.is_some());
+        if pending.is_empty() {
+            return;
+        }
+
         widget.set_sensitive(false);
-        let cpus = num_cpus::get();
-        if cpus < corners.len() {
-            let corners_per_thread = corners.len() / cpus;
-            let remainder_corners = corners.len() % cpus;
-            for i in 0..cpus {
-                let tx = tx.clone();
-                let copy_corners = corners.clone();
-                let inp = inputfile.clone();
-                thread::spawn(move || {
-                    genetic::algorithm(
-                        inp,
-                        &copy_corners[i * corners_per_thread..=(i + 1) * corners_per_thread],
-                        &tx,
-                    );
-                });
-            }
-            if remainder_corners > 1 {
-                let tx = tx.clone();
-                let copy_corners = corners.clone();
-                let inp = inputfile.clone();
-                thread::spawn(move || {
-                    genetic::algorithm(inp, &copy_corners[cpus * corners_per_thread..], &tx);
-                });
-            }
-        } else {
+        s.set_sensitive(true);
+        r.set(r.get() + 1);
+        e.borrow_mut().clear();
+        let id = r.get();
+        let token = Arc::new(AtomicBool::new(false));
+        *k.borrow_mut() = token.clone();
+        for (segment, previous) in pending {
             let tx = tx.clone();
-            let copy_corners = corners.clone();
-            thread::spawn(move || {
-                genetic::algorithm(inputfile, &copy_corners, &tx);
+            let image = im.clone();
+            let token = token.clone();
+            let optimizer = optimizer.clone();
+            pool.spawn(move || {
+                optimizer.optimize(&image, segment, previous, &config, &token, &|event| {
+                    tx.send((id, event)).unwrap()
+                });
             });
         }
     });
 
+    /* Stop */
+    let r = run.clone();
+    let k = cancel.clone();
+    let g = go.clone();
+    let e = evolving.clone();
+    stop.connect_clicked(move |widget| {
+        k.borrow().store(true, Ordering::Relaxed);
+        r.set(r.get() + 1);
+        e.borrow_mut().clear();
+        widget.set_sensitive(false);
+        g.set_sensitive(true);
+    });
+
     /* Drawing */
 
-    let ifile = inputfile.clone();
     let c = corners.clone();
     let l = lines.clone();
+    let p = preview.clone();
+    let e = evolving.clone();
+    let v = live.clone();
     drawing.connect_draw(move |_widget, cr| {
         let corners = c.clone();
         let corners = corners.borrow();
         let lines = l.clone();
         let lines = lines.borrow();
 
-        let img = gdk_pixbuf::Pixbuf::from_file(ifile.clone()).unwrap();
-        cr.set_source_pixbuf(&img, 0.0, 0.0);
+        if p.is_active() {
+            cr.set_source_pixbuf(&binarized, 0.0, 0.0);
+        } else {
+            cr.set_source_pixbuf(&background, 0.0, 0.0);
+        }
         cr.paint().unwrap();
 
         cr.set_source_rgb(1.0, 0.0, 0.0);
-        for corner in corners.iter() {
+        for corner in corners.iter().flatten() {
             cr.arc(
                 corner.x as f64,
                 corner.y as f64,
@@ -224,7 +866,22 @@ fn main() {
         }
 
         for line in lines.iter() {
-            draw_bezier(cr, &line);
+            draw_bezier(cr, &line.curve);
+        }
+
+        if v.is_active() {
+            for (best, sample) in e.borrow().values() {
+                cr.set_source_rgba(0.0, 0.6, 0.0, 0.25);
+                cr.set_line_width(1.0);
+                for line in sample {
+                    bezier_path(cr, line);
+                }
+                cr.stroke().unwrap();
+                cr.set_source_rgb(1.0, 0.5, 0.0);
+                cr.set_line_width(2.0);
+                bezier_path(cr, best);
+                cr.stroke().unwrap();
+            }
         }
 
         Inhibit(false)
@@ -240,7 +897,11 @@ fn main() {
             let (x, y) = event.position();
 
             if event.button() == 1 {
-                corners.borrow_mut().push(Corner {
+                let mut corners = corners.borrow_mut();
+                if corners.is_empty() {
+                    corners.push(Vec::new());
+                }
+                corners.last_mut().unwrap().push(Corner {
                     x: x as u32,
                     y: y as u32,
                     score: f32::INFINITY,
@@ -264,20 +925,69 @@ fn main() {
 
     /* Idle */
     let g = go.clone();
+    let s = stop.clone();
     let p = progress.clone();
     let d = drawing.clone();
     let l = lines.clone();
-    let c = corners.clone();
+    let r = run.clone();
+    let e = evolving.clone();
+    /* Generación actual de los segmentos que se están calculando */
+    let mut running: HashMap<(usize, usize), usize> = HashMap::new();
+    let mut current = 0;
     gtk::glib::source::idle_add_local(move || {
         let lines = l.clone();
-        let corners = c.clone();
-        if let Ok(line) = rx.try_recv() {
-            lines.borrow_mut().push(line);
-            p.set_fraction((lines.borrow().len() as f64) / (corners.borrow().len() as f64 - 1.0));
-            if lines.borrow().len() == corners.borrow().len() - 1 {
-                g.set_sensitive(true)
+        while let Ok((id, event)) = rx.try_recv() {
+            if id != r.get() {
+                continue;
             }
-        };
+            if id != current {
+                running.clear();
+                current = id;
+            }
+            if let Some(log) = log.as_mut() {
+                writeln!(log, "{}", event).unwrap();
+            }
+            if let Some(stats) = stats.as_mut() {
+                stats.write(&event);
+            }
+            let mut lines = lines.borrow_mut();
+            match event {
+                genetic::Event::Started(segment) => {
+                    running.insert((segment.contour, segment.index), 0);
+                }
+                genetic::Event::Generation {
+                    segment,
+                    generation,
+                    ..
+                } => {
+                    running.insert((segment.contour, segment.index), generation);
+                }
+                genetic::Event::Snapshot {
+                    segment,
+                    best,
+                    sample,
+                    ..
+                } => {
+                    e.borrow_mut()
+                        .insert((segment.contour, segment.index), (best, sample));
+                }
+                genetic::Event::Finished(line) => {
+                    running.remove(&(line.contour, line.index));
+                    e.borrow_mut().remove(&(line.contour, line.index));
+                    lines.insert(line);
+                    if lines.len() >= lines.total() {
+                        g.set_sensitive(true);
+                        s.set_sensitive(false);
+                    }
+                }
+            }
+            /* Los segmentos en curso cuentan según las generaciones que llevan */
+            let partial: f64 = running
+                .values()
+                .map(|generation| *generation as f64 / config.max_generations as f64)
+                .sum();
+            p.set_fraction((lines.len() as f64 + partial) / (lines.total() as f64));
+        }
         d.queue_draw();
         gtk::glib::Continue(true)
     });
@@ -288,6 +998,11 @@ fn main() {
 fn draw_bezier(cr: &cairo::Context, line: &bezier::Bezier) {
     cr.set_source_rgb(0.0, 0.0, 1.0);
     cr.set_line_width(3.0);
+    bezier_path(cr, line);
+    cr.stroke().unwrap();
+}
+
+fn bezier_path(cr: &cairo::Context, line: &bezier::Bezier) {
     cr.move_to(line.start.x, line.start.y);
     cr.curve_to(
         line.control1.x,
@@ -297,5 +1012,4 @@ fn draw_bezier(cr: &cairo::Context, line: &bezier::Bezier) {
         line.end.x,
         line.end.y,
     );
-    cr.stroke().unwrap();
 }
@@ -0,0 +1,86 @@
+/*
+ *  This file is part of Mendel Vectorizer.
+ *
+ *  Mendel Vectorizer is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  Mendel Vectorizer is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with Mendel Vectorizer.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use crate::bezier::Bezier;
+use crate::cmaes;
+use crate::fit;
+use crate::genetic::{self, Event, GeneticConfig, Picture, SegmentResult};
+use crate::segment::Segment;
+
+use std::sync::atomic::AtomicBool;
+
+/* Busca la curva de un segmento. Todos usan el mismo fitness y criterios de parada de GeneticConfig */
+pub trait Optimizer: Send + Sync {
+    fn optimize(
+        &self,
+        image: &Picture,
+        segment: Segment,
+        previous: Option<Bezier>,
+        config: &GeneticConfig,
+        cancel: &AtomicBool,
+        progress: &dyn Fn(Event),
+    ) -> Option<SegmentResult>;
+}
+
+pub struct Genetic;
+
+impl Optimizer for Genetic {
+    fn optimize(
+        &self,
+        image: &Picture,
+        segment: Segment,
+        previous: Option<Bezier>,
+        config: &GeneticConfig,
+        cancel: &AtomicBool,
+        progress: &dyn Fn(Event),
+    ) -> Option<SegmentResult> {
+        genetic::algorithm(image, segment, previous, config, cancel, progress)
+    }
+}
+
+pub struct CmaEs;
+
+impl Optimizer for CmaEs {
+    fn optimize(
+        &self,
+        image: &Picture,
+        segment: Segment,
+        previous: Option<Bezier>,
+        config: &GeneticConfig,
+        cancel: &AtomicBool,
+        progress: &dyn Fn(Event),
+    ) -> Option<SegmentResult> {
+        cmaes::algorithm(image, segment, previous, config, cancel, progress)
+    }
+}
+
+/* Sin búsqueda, mínimos cuadrados sobre el trazo. No usa la curva anterior */
+pub struct LeastSquares;
+
+impl Optimizer for LeastSquares {
+    fn optimize(
+        &self,
+        image: &Picture,
+        segment: Segment,
+        _previous: Option<Bezier>,
+        config: &GeneticConfig,
+        cancel: &AtomicBool,
+        progress: &dyn Fn(Event),
+    ) -> Option<SegmentResult> {
+        fit::algorithm(image, segment, config, cancel, progress)
+    }
+}
@@ -0,0 +1,146 @@
+/*
+ *  This file is part of Mendel Vectorizer.
+ *
+ *  Mendel Vectorizer is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  Mendel Vectorizer is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with Mendel Vectorizer.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use image::imageops::{resize, FilterType};
+use image::{GrayImage, Luma};
+use imageproc::contrast::stretch_contrast;
+use imageproc::filter::{gaussian_blur_f32, median_filter};
+use imageproc::region_labelling::{connected_components, Connectivity};
+use imageproc::stats::percentile;
+
+use crate::genetic::{self, GeneticConfig};
+
+#[derive(Default)]
+pub struct PreprocessOptions {
+    pub scale: Option<f64>,
+    pub gaussian_blur: Option<f32>,
+    pub median_blur: Option<u32>,
+    pub normalize: bool,
+    /* Radio de la apertura y el cierre morfológicos, respecto al dibujo */
+    pub open: Option<u32>,
+    pub close: Option<u32>,
+    /* Los dibujos son claros sobre fondo oscuro */
+    pub invert: bool,
+}
+
+impl PreprocessOptions {
+    pub fn is_active(&self) -> bool {
+        self.scale.is_some()
+            || self.gaussian_blur.is_some()
+            || self.median_blur.is_some()
+            || self.normalize
+            || self.open.is_some()
+            || self.close.is_some()
+    }
+}
+
+pub fn preprocess(mut image: GrayImage, options: &PreprocessOptions) -> GrayImage {
+    if let Some(scale) = options.scale {
+        let width = ((image.width() as f64 * scale).round() as u32).max(1);
+        let height = ((image.height() as f64 * scale).round() as u32).max(1);
+        image = resize(&image, width, height, FilterType::Triangle);
+    }
+    if let Some(sigma) = options.gaussian_blur {
+        image = gaussian_blur_f32(&image, sigma);
+    }
+    if let Some(radius) = options.median_blur {
+        image = median_filter(&image, radius, radius);
+    }
+    if options.normalize {
+        /* Se ignoran los extremos para que unos pocos píxeles no fijen el rango */
+        let lower = percentile(&image, 1);
+        let upper = percentile(&image, 99);
+        if lower < upper {
+            image = stretch_contrast(&image, lower, upper);
+        }
+    }
+    if let Some(radius) = options.open {
+        let eroded = erode(&image, radius, options.invert);
+        image = dilate(&eroded, radius, options.invert);
+    }
+    if let Some(radius) = options.close {
+        let dilated = dilate(&image, radius, options.invert);
+        image = erode(&dilated, radius, options.invert);
+    }
+    image
+}
+
+/* Borra las manchas del dibujo con menos de min_area píxeles */
+pub fn despeckle(image: &GrayImage, min_area: u32, config: &GeneticConfig) -> GrayImage {
+    let dark = genetic::binarize(image, config);
+    let mask = GrayImage::from_fn(dark.width(), dark.height(), |x, y| {
+        Luma([255 - dark.get_pixel(x, y)[0]])
+    });
+    let labels = connected_components(&mask, Connectivity::Eight, Luma([0]));
+
+    let mut areas = Vec::new();
+    for label in labels.pixels() {
+        let label = label[0] as usize;
+        if label >= areas.len() {
+            areas.resize(label + 1, 0);
+        }
+        areas[label] += 1;
+    }
+
+    let background = if config.invert { 0 } else { 255 };
+    let mut clean = image.clone();
+    for (x, y, label) in labels.enumerate_pixels() {
+        let label = label[0] as usize;
+        if label != 0 && areas[label] < min_area {
+            clean.put_pixel(x, y, Luma([background]));
+        }
+    }
+    clean
+}
+
+/* El dibujo es oscuro: erosionarlo es quedarse con el máximo y dilatarlo con el mínimo */
+fn erode(image: &GrayImage, radius: u32, invert: bool) -> GrayImage {
+    if invert {
+        window_filter(image, radius, u8::min)
+    } else {
+        window_filter(image, radius, u8::max)
+    }
+}
+
+fn dilate(image: &GrayImage, radius: u32, invert: bool) -> GrayImage {
+    if invert {
+        window_filter(image, radius, u8::max)
+    } else {
+        window_filter(image, radius, u8::min)
+    }
+}
+
+/* Ventana cuadrada, separable en horizontal y vertical */
+fn window_filter(image: &GrayImage, radius: u32, f: fn(u8, u8) -> u8) -> GrayImage {
+    let (width, height) = image.dimensions();
+    let horizontal = GrayImage::from_fn(width, height, |x, y| {
+        let from = x.saturating_sub(radius);
+        let to = (x + radius).min(width - 1);
+        Luma([(from..=to)
+            .map(|i| image.get_pixel(i, y)[0])
+            .reduce(f)
+            .unwrap()])
+    });
+    GrayImage::from_fn(width, height, |x, y| {
+        let from = y.saturating_sub(radius);
+        let to = (y + radius).min(height - 1);
+        Luma([(from..=to)
+            .map(|j| horizontal.get_pixel(x, j)[0])
+            .reduce(f)
+            .unwrap()])
+    })
+}
@@ -0,0 +1,139 @@
+/*
+ *  This file is part of Mendel Vectorizer.
+ *
+ *  Mendel Vectorizer is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  Mendel Vectorizer is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *
+ *  You should have received a copy of the GNU General Public License
+ *  along with Mendel Vectorizer.  If not, see <https://www.gnu.org/licenses/>.
+*/
+
+use imageproc::corners::Corner;
+
+use crate::bezier::{Bezier, Point};
+use crate::genetic::SegmentResult;
+
+#[derive(Copy, Clone)]
+pub struct Segment {
+    pub contour: usize,
+    pub index: usize,
+    pub start: Point,
+    pub end: Point,
+}
+
+pub fn segments(corners: &[Vec<Corner>], closed: bool) -> Vec<Segment> {
+    let point = |corner: &Corner| Point {
+        x: corner.x as f64,
+        y: corner.y as f64,
+    };
+    let mut segments = Vec::new();
+    for (contour, corners) in corners.iter().enumerate() {
+        let mut ends: Vec<(&Corner, &Corner)> =
+            corners.windows(2).map(|w| (&w[0], &w[1])).collect();
+        /* Segmento de vuelta a la primera esquina, salvo si ya se repite al final (SVG importado) */
+        if closed && corners.len() > 2 {
+            let (first, last) = (&corners[0], &corners[corners.len() - 1]);
+            if (first.x, first.y) != (last.x, last.y) {
+                ends.push((last, first));
+            }
+        }
+        segments.extend(
+            ends.into_iter()
+                .enumerate()
+                .map(|(index, (start, end))| Segment {
+                    contour,
+                    index,
+                    start: point(start),
+                    end: point(end),
+                }),
+        );
+    }
+    segments
+}
+
+/* Un hueco por segmento, así los resultados quedan en el orden de los contornos
+ * aunque los hilos terminen en otro orden */
+#[derive(Default)]
+pub struct Results {
+    slots: Vec<Vec<Option<SegmentResult>>>,
+}
+
+impl Results {
+    pub fn new() -> Results {
+        Results::default()
+    }
+
+    pub fn insert(&mut self, result: SegmentResult) {
+        if self.slots.len() <= result.contour {
+            self.slots.resize_with(result.contour + 1, Vec::new);
+        }
+        let (contour, index) = (result.contour, result.index);
+        let slots = &mut self.slots[contour];
+        if slots.len() <= index {
+            slots.resize_with(index + 1, || None);
+        }
+        slots[index] = Some(result);
+    }
+
+    /* Ajusta los huecos a los segmentos actuales y devuelve los que hay que calcular,
+     * con la curva anterior si no convergió. Solo se conservan los resultados que han convergido */
+    pub fn pending(
+        &mut self,
+        corners: &[Vec<Corner>],
+        closed: bool,
+    ) -> Vec<(Segment, Option<Bezier>)> {
+        let mut previous: Vec<SegmentResult> = self.slots.drain(..).flatten().flatten().collect();
+
+        let mut pending = Vec::new();
+        for segment in segments(corners, closed) {
+            let found = previous.iter().position(|line| {
+                line.curve.start == segment.start && line.curve.end == segment.end
+            });
+            let line = found.map(|i| previous.swap_remove(i));
+            match line {
+                Some(mut line) if line.converged => {
+                    line.contour = segment.contour;
+                    line.index = segment.index;
+                    self.insert(line);
+                }
+                line => {
+                    /* Hueco vacío */
+                    if self.slots.len() <= segment.contour {
+                        self.slots.resize_with(segment.contour + 1, Vec::new);
+                    }
+                    self.slots[segment.contour].push(None);
+                    pending.push((segment, line.map(|line| line.curve)));
+                }
+            }
+        }
+        pending
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &SegmentResult> {
+        self.slots.iter().flatten().flatten()
+    }
+
+    pub fn to_vec(&self) -> Vec<SegmentResult> {
+        self.iter().cloned().collect()
+    }
+
+    /* Segmentos terminados */
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    pub fn total(&self) -> usize {
+        self.slots.iter().map(|contour| contour.len()).sum()
+    }
+
+    pub fn clear(&mut self) {
+        self.slots.clear();
+    }
+}